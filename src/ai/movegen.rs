@@ -0,0 +1,290 @@
+use crate::game::engine::Action;
+use crate::game::field::Field;
+use crate::game::score::TSpin;
+use crate::game::tetromino::{Kind, Rotation, Tetromino};
+use crate::game::*;
+use std::collections::{HashSet, VecDeque};
+
+// 固定できる置き場所と、今の位置からそこまでの操作
+#[derive(Clone, Debug)]
+pub struct Placement {
+    pub tetromino: Tetromino,
+    pub pos: Position,
+    pub last_rotation: Option<(Rotation, usize)>, // Tスピン判定に使う
+    pub t_spin: Option<TSpin>,
+    pub path: Vec<Action>, // 最後のHardDropまで(それぞれ1フレームで押して離す)
+}
+
+impl Placement {
+    // 操作中のテトリミノを置き場所に移す(固定はしない)
+    pub fn apply(&self, game: &mut Game) {
+        game.tetromino = self.tetromino;
+        game.pos = self.pos;
+        game.last_rotation = self.last_rotation;
+    }
+
+    // 埋めるマスの座標(上から順)
+    pub fn cells(&self) -> [(usize, usize); 4] {
+        cells(&self.pos, &self.tetromino)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    tetromino: Tetromino,
+    pos: Position,
+    last_rotation: Option<(Rotation, usize)>,
+}
+
+// 直前の操作の区別(Tスピンの判定が変わるのはTだけ)
+const SPINS: usize = 3;
+
+impl Node {
+    // 訪問済みの表での位置
+    fn index(&self, field: &Field) -> usize {
+        let spin = match (self.tetromino.kind(), self.last_rotation) {
+            (Kind::T, Some((rotation, kick))) if is_tst_kick(rotation, kick) => 2,
+            (Kind::T, Some(_)) => 1,
+            _ => 0,
+        };
+        let rotate_state = self.tetromino.rotate_state() as usize;
+        ((self.pos.y * field.width() + self.pos.x) * 4 + rotate_state) * SPINS + spin
+    }
+}
+
+const MOVES: [Action; 7] = [
+    Action::Left,
+    Action::Right,
+    Action::SoftDrop,
+    Action::SonicDrop,
+    Action::RotateRight,
+    Action::RotateLeft,
+    Action::Rotate180,
+];
+
+// 1回の操作で移れる先(動けなければNone)
+fn next(field: &Field, node: &Node, action: Action) -> Option<Node> {
+    let Node { tetromino, pos, .. } = *node;
+    let pos = match action {
+        Action::Left | Action::Right => Position {
+            x: match action {
+                Action::Left => pos.x.checked_sub(1)?,
+                _ => pos.x + 1,
+            },
+            y: pos.y,
+        },
+        Action::SoftDrop => Position {
+            x: pos.x,
+            y: pos.y + 1,
+        },
+        Action::SonicDrop => hard_drop_pos(field, &pos, &tetromino),
+        Action::RotateRight | Action::RotateLeft | Action::Rotate180 => {
+            let rotation = match action {
+                Action::RotateRight => Rotation::Right,
+                Action::RotateLeft => Rotation::Left,
+                _ => Rotation::Half,
+            };
+            let (tetromino, pos, kick) = try_rotate(field, &pos, &tetromino, rotation)?;
+            return Some(Node {
+                tetromino,
+                pos,
+                last_rotation: Some((rotation, kick)),
+            });
+        }
+        _ => return None,
+    };
+    if pos == node.pos || is_collision(field, &pos, &tetromino) {
+        return None;
+    }
+    Some(Node {
+        tetromino,
+        pos,
+        last_rotation: None,
+    })
+}
+
+fn cells(pos: &Position, tetromino: &Tetromino) -> [(usize, usize); 4] {
+    let mask = tetromino.shape_mask();
+    let mut cells = [(0, 0); 4];
+    let mut i = 0;
+    for (y, row) in mask.iter().enumerate() {
+        for x in 0..4 {
+            if row & 1 << x != 0 {
+                cells[i] = (pos.x + x, pos.y + y);
+                i += 1;
+            }
+        }
+    }
+    cells
+}
+
+// 左右移動、1段落下、着地位置までの落下、壁蹴りを含む回転で行ける状態を幅優先で探し、
+// 着地している状態を固定できる置き場所とする
+// 埋めるマスとTスピンの判定が同じ置き場所は、操作の少ないものだけを返す
+// 操作中の自動落下とロックダウンは考えない(接地したままの移動・回転が
+// リセット回数の上限を超える経路や、落下の速いレベルではその通りに動かせないことがある)
+pub fn placements(field: &Field, pos: &Position, tetromino: &Tetromino) -> Vec<Placement> {
+    if is_collision(field, pos, tetromino) {
+        return Vec::new();
+    }
+    let start = Node {
+        tetromino: *tetromino,
+        pos: *pos,
+        last_rotation: None,
+    };
+    let mut nodes = vec![(start, None)]; // (状態, (直前の状態の番号, 操作))
+    let mut seen = vec![false; field.width() * field.height() * 4 * SPINS];
+    seen[start.index(field)] = true;
+    let mut queue = VecDeque::from([0]);
+    while let Some(i) = queue.pop_front() {
+        let node = nodes[i].0;
+        for action in MOVES {
+            let Some(next) = next(field, &node, action) else {
+                continue;
+            };
+            // 同じ状態なら先に見つけた方が操作が少ない
+            let index = next.index(field);
+            if seen[index] {
+                continue;
+            }
+            seen[index] = true;
+            queue.push_back(nodes.len());
+            nodes.push((next, Some((i, action))));
+        }
+    }
+
+    let mut found = HashSet::new();
+    let mut placements = Vec::new();
+    for i in 0..nodes.len() {
+        let node = nodes[i].0;
+        let below = Position {
+            x: node.pos.x,
+            y: node.pos.y + 1,
+        };
+        if !is_collision(field, &below, &node.tetromino) {
+            continue;
+        }
+        let t_spin = t_spin(field, &node.pos, &node.tetromino, node.last_rotation);
+        if !found.insert((cells(&node.pos, &node.tetromino), t_spin)) {
+            continue;
+        }
+        let mut path = vec![Action::HardDrop];
+        let mut j = i;
+        while let Some((parent, action)) = nodes[j].1 {
+            path.push(action);
+            j = parent;
+        }
+        path.reverse();
+        placements.push(Placement {
+            tetromino: node.tetromino,
+            pos: node.pos,
+            last_rotation: node.last_rotation,
+            t_spin,
+            path,
+        });
+    }
+    placements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::search::{search, SearchConfig};
+    use crate::game::engine::{Engine, Input, InputConfig};
+    use crate::game::event::GameEvent;
+    use crate::game::lock_delay::LockDelay;
+
+    // 操作を1つずつ押して離し、最後のHardDropで置き場所に固定されるか
+    fn assert_path(game: &Game, placement: &Placement) {
+        let mut expected = game.field.clone();
+        put_tetromino(&mut expected, &placement.pos, &placement.tetromino);
+        erase_line(&mut expected);
+
+        let mut engine = Engine::new(game.clone(), InputConfig::default());
+        let (last, actions) = placement.path.split_last().unwrap();
+        assert_eq!(*last, Action::HardDrop);
+        let locked = |events: &[GameEvent]| {
+            events.iter().find_map(|event| match event {
+                GameEvent::PieceLocked { t_spin, .. } => Some(*t_spin),
+                _ => None,
+            })
+        };
+        for &action in actions {
+            let events = engine.step(&[Input::Press(action), Input::Release(action)]);
+            assert!(locked(&events).is_none(), "locked before hard drop");
+        }
+        let events = engine.step(&[
+            Input::Press(Action::HardDrop),
+            Input::Release(Action::HardDrop),
+        ]);
+        assert_eq!(locked(&events), Some(placement.t_spin));
+        assert_eq!(engine.game.field, expected);
+    }
+
+    #[test]
+    fn paths_reach_placements() {
+        let config = SearchConfig {
+            depth: 1,
+            width: 1,
+            time_budget: None,
+        };
+        for seed in 0..2 {
+            let mut engine = Engine::new(
+                Game::new(&GameConfig {
+                    seed,
+                    ..Default::default()
+                }),
+                InputConfig::default(),
+            );
+            for _ in 0..40 {
+                let game = &engine.game;
+                for placement in placements(&game.field, &game.pos, &game.tetromino) {
+                    assert_path(game, &placement);
+                }
+                engine.game = search(&engine.game, &config);
+                engine.step(&[
+                    Input::Press(Action::HardDrop),
+                    Input::Release(Action::HardDrop),
+                ]);
+                if engine.is_finished() {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn t_spin_double_slot() {
+        let mut game = Game::new(&GameConfig::default());
+        let field = &mut game.field;
+        let bottom = field.floor() - 1;
+        let center = field.columns().start + 4;
+        for x in field.columns() {
+            if x != center {
+                field.set(x, bottom, cell::GARBAGE);
+            }
+            if !(center - 1..=center + 1).contains(&x) {
+                field.set(x, bottom - 1, cell::GARBAGE);
+            }
+            if x < center {
+                field.set(x, bottom - 2, cell::GARBAGE);
+            }
+        }
+        game.tetromino = Tetromino::new(Kind::T, game.rotation_system);
+        game.pos = spawn_position(&game.field, &game.tetromino);
+        game.lock_delay = LockDelay::new(game.pos.y);
+
+        let placements = placements(&game.field, &game.pos, &game.tetromino);
+        let t_spin = placements
+            .iter()
+            .find(|placement| {
+                placement.t_spin == Some(TSpin::Full)
+                    && placement.cells().iter().any(|&(_, y)| y == bottom)
+            })
+            .expect("no T-spin double placement");
+        assert_path(&game, t_spin);
+        for placement in &placements {
+            assert_path(&game, placement);
+        }
+    }
+}
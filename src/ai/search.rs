@@ -0,0 +1,261 @@
+use super::evaluate;
+use super::movegen::{self, Placement};
+use crate::game::field::Field;
+use crate::game::rotation_system::RotationSystem;
+use crate::game::tetromino::{Kind, Tetromino};
+use crate::game::*;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug)]
+pub struct SearchConfig {
+    pub depth: usize, // 先読みするテトリミノの数(1なら操作中のものだけ)
+    pub width: usize, // 深さごとに残す候補の数
+    // 1手にかける時間(超えたら読み終えた深さまでで決める)
+    // 実時間で打ち切るので、指定すると同じ局面でも結果がマシンの速さや負荷で変わる
+    pub time_budget: Option<Duration>,
+}
+
+impl Default for SearchConfig {
+    fn default() -> SearchConfig {
+        SearchConfig {
+            depth: 3,
+            width: 8,
+            time_budget: None,
+        }
+    }
+}
+
+// 探索中の局面(ラインは消去済み)
+struct Node {
+    field: Field,
+    current: Option<Kind>, // 次に置くテトリミノ(NEXTを使い切ったらNone)
+    hold: Option<Kind>,
+    next: usize,  // まだ出ていないNEXTの先頭
+    reward: f64,  // ここまでに消したラインの評価の合計
+    value: f64,   // reward+最後に置いた直後の積み方の評価
+    first: usize, // 1手目の番号
+}
+
+// 置く前の状態から、置き場所ごとの子を作る
+struct Parent<'a> {
+    field: &'a Field,
+    hold: Option<Kind>,
+    next: usize,
+    reward: f64,
+}
+
+impl Parent<'_> {
+    fn child(&self, placement: &Placement, queue: &[Kind], first: usize) -> Node {
+        let mut field = self.field.clone();
+        put_tetromino(&mut field, &placement.pos, &placement.tetromino);
+        let (lines, shape) = evaluate(&field);
+        erase_line(&mut field);
+        let reward = self.reward + lines;
+        Node {
+            field,
+            current: queue.get(self.next).copied(),
+            hold: self.hold,
+            next: self.next + 1,
+            reward,
+            value: reward + shape,
+            first,
+        }
+    }
+}
+
+impl Node {
+    // ホールドするかどうかと置き場所の組み合わせで次の局面を作る
+    fn expand(&self, queue: &[Kind], rotation_system: &'static RotationSystem) -> Vec<Node> {
+        let Some(current) = self.current else {
+            return Vec::new();
+        };
+        let mut children = Vec::new();
+        for do_hold in [true, false] {
+            // 同じ種類と入れ替えても変わらない
+            if do_hold && self.hold == Some(current) {
+                continue;
+            }
+            let (kind, next) = match (do_hold, self.hold) {
+                (false, _) => (current, self.next),
+                (true, Some(hold)) => (hold, self.next),
+                (true, None) => match queue.get(self.next) {
+                    Some(&kind) => (kind, self.next + 1),
+                    None => continue,
+                },
+            };
+            let parent = Parent {
+                field: &self.field,
+                hold: if do_hold { Some(current) } else { self.hold },
+                next,
+                reward: self.reward,
+            };
+            let tetromino = Tetromino::new(kind, rotation_system);
+            let pos = spawn_position(&self.field, &tetromino);
+            for placement in movegen::placements(&self.field, &pos, &tetromino) {
+                children.push(parent.child(&placement, queue, self.first));
+            }
+        }
+        children
+    }
+}
+
+// 評価の高い順にwidth個残す(同じ評価なら先に作ったもの)
+fn prune(mut nodes: Vec<Node>, width: usize) -> Vec<Node> {
+    nodes.sort_by(|a, b| b.value.total_cmp(&a.value));
+    nodes.truncate(width.max(1));
+    nodes
+}
+
+// NEXTとホールドを使ってdepth個先までビームサーチし、最初の1手を適用したゲームを返す(固定はしない)
+pub fn search(game: &Game, config: &SearchConfig) -> Game {
+    let start = Instant::now();
+    let is_over = || {
+        config
+            .time_budget
+            .is_some_and(|budget| start.elapsed() >= budget)
+    };
+    let queue: Vec<Kind> = game.next_tetrominoes.iter().map(|t| t.kind()).collect();
+
+    // 1手目は実際にホールドして、出現位置やホールド済みかどうかをゲームと合わせる
+    let mut roots = Vec::new(); // ホールドした/しなかったゲーム
+    let mut moves = Vec::new(); // (rootsの番号, 置き場所)
+    let mut beam = Vec::new();
+    for do_hold in [true, false] {
+        if do_hold && game.held {
+            continue;
+        }
+        let mut game = game.clone();
+        let next = usize::from(do_hold && game.hold_tetromino.is_none());
+        // ホールドするとブロックアウトになるなら選ばない
+        if do_hold && hold(&mut game).is_err() {
+            continue;
+        }
+        let parent = Parent {
+            field: &game.field,
+            hold: game.hold_tetromino.map(|t| t.kind()),
+            next,
+            reward: 0.0,
+        };
+        for placement in movegen::placements(&game.field, &game.pos, &game.tetromino) {
+            beam.push(parent.child(&placement, &queue, moves.len()));
+            moves.push((roots.len(), placement));
+        }
+        roots.push(game);
+    }
+    if moves.is_empty() {
+        return game.clone();
+    }
+    beam = prune(beam, config.width);
+
+    // 時間切れになったら途中まで読んだ深さは使わない
+    'search: for _ in 1..config.depth {
+        let mut children = Vec::new();
+        for node in &beam {
+            if is_over() {
+                break 'search;
+            }
+            children.extend(node.expand(&queue, game.rotation_system));
+        }
+        if children.is_empty() {
+            break;
+        }
+        beam = prune(children, config.width);
+    }
+
+    let (root, placement) = &moves[beam[0].first];
+    let mut game = roots.swap_remove(*root);
+    placement.apply(&mut game);
+    game
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::engine::{Action, Engine, Input, InputConfig};
+
+    // 選んだ手で置いたあとのフィールドとホールド
+    fn landed(game: &Game) -> (Field, Option<Kind>) {
+        let mut field = game.field.clone();
+        put_tetromino(&mut field, &game.pos, &game.tetromino);
+        (field, game.hold_tetromino.map(|t| t.kind()))
+    }
+
+    fn node(value: f64, first: usize) -> Node {
+        Node {
+            field: Field::default(),
+            current: None,
+            hold: None,
+            next: 0,
+            reward: 0.0,
+            value,
+            first,
+        }
+    }
+
+    #[test]
+    fn prune_keeps_best_in_order() {
+        let nodes = [1.0, 3.0, 2.0, 3.0]
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| node(value, i))
+            .collect();
+        let firsts: Vec<usize> = prune(nodes, 3).iter().map(|n| n.first).collect();
+        assert_eq!(firsts, [1, 3, 2]);
+
+        // 幅0でも1つは残す
+        assert_eq!(prune(vec![node(0.0, 0), node(1.0, 1)], 0).len(), 1);
+    }
+
+    #[test]
+    fn same_seed_same_placement() {
+        let config = SearchConfig::default();
+        let game_config = GameConfig {
+            seed: 1,
+            ..Default::default()
+        };
+        let mut engines =
+            [(); 2].map(|_| Engine::new(Game::new(&game_config), InputConfig::default()));
+        for _ in 0..10 {
+            for engine in &mut engines {
+                engine.game = search(&engine.game, &config);
+            }
+            assert_eq!(landed(&engines[0].game), landed(&engines[1].game));
+            for engine in &mut engines {
+                engine.step(&[
+                    Input::Press(Action::HardDrop),
+                    Input::Release(Action::HardDrop),
+                ]);
+            }
+            if engines[0].is_finished() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn time_up_falls_back_to_first_depth() {
+        let game = Game::new(&GameConfig::default());
+        let shallow = SearchConfig {
+            depth: 1,
+            ..Default::default()
+        };
+        let time_up = SearchConfig {
+            time_budget: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        assert_eq!(
+            landed(&search(&game, &time_up)),
+            landed(&search(&game, &shallow))
+        );
+    }
+
+    #[test]
+    fn no_hold_after_holding() {
+        let mut game = Game::new(&GameConfig::default());
+        hold(&mut game).unwrap();
+        let hold_kind = game.hold_tetromino.map(|t| t.kind());
+        let game = search(&game, &SearchConfig::default());
+        assert_eq!(game.hold_tetromino.map(|t| t.kind()), hold_kind);
+        assert!(game.held);
+    }
+}
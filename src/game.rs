@@ -1,108 +1,191 @@
 pub mod cell;
+pub mod das;
+pub mod engine;
+pub mod event;
+pub mod field;
+pub mod garbage;
+pub mod gravity;
+pub mod lock_delay;
+pub mod randomizer;
+pub mod rotation_system;
+pub mod score;
+pub mod statistics;
 pub mod tetromino;
-use crate::game::tetromino::gen_tetromino_7;
-use cell::WALL as W;
+use event::{ClearKind, GameEvent};
+use field::Field;
+use garbage::{Garbage, GarbageConfig};
+use lock_delay::LockDelay;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use randomizer::{AnyRandomizer, Randomizer, RandomizerKind};
+use rotation_system::RotationSystem;
+use score::TSpin;
+use serde::{Deserialize, Serialize};
+use statistics::Statistics;
 use std::collections::VecDeque;
-use tetromino::Tetromino;
+use std::time::Duration;
+use tetromino::{Kind, Rotation, Tetromino};
 
 use self::tetromino::WallKickOffsets;
 
-pub const FIELD_WIDTH: usize = 10 + 2 + 2; // フィールド横幅+壁+番兵
-pub const FIELD_HEIGHT: usize = 20 + 1 + 1 + 1; // フィールド縦幅+床+天井+番兵
-
 pub const NEXT_TETROMINOES_SIZE: usize = 3;
 
-pub const SCORE_TABLE: [usize; 5] = [0, 1, 5, 25, 100];
-
-pub type FieldSize = [[cell::Kind; FIELD_WIDTH]; FIELD_HEIGHT];
+pub const DIG_LINES: usize = 10; // 掘りモードでフィールドに積んでおくせり上がりの段数(可視領域の半分まで)
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
 }
 
 impl Position {
-    pub fn init() -> Position {
-        Position { x: 5, y: 1 }
+    // 可視領域の上の2段に、左右の中央(奇数幅なら左寄り)に出現
+    pub fn init(field: &Field) -> Position {
+        Position {
+            x: field.columns().start + (field.playfield_width() - 4) / 2,
+            y: field.buffer_height() - 2,
+        }
     }
 }
 
-#[derive(Clone)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameOverReason {
+    BlockOut, // 出現位置が塞がれている
+    LockOut,  // 可視領域より上で固定された
+    TopOut,   // ブロックがバッファ領域の上まであふれた
+}
+
+// ゲームの終了条件
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Goal {
+    Endless,        // ゲームオーバーまで続ける
+    Lines(usize),   // 指定のライン数を消したら終了(スプリント)
+    Time(Duration), // 制限時間まで(ウルトラ)
+    Garbage(usize), // せり上がりを指定の段数消したら終了(掘り)
+}
+
+#[derive(Clone, Debug)]
+pub struct GameConfig {
+    pub level: usize,
+    pub seed: u64, // 同じシードなら同じ順番でテトリミノが出現する
+    pub randomizer: RandomizerKind,
+    pub rotation_system: &'static RotationSystem,
+    pub goal: Goal,
+    pub garbage: GarbageConfig,
+    pub width: usize, // 可視領域の大きさ
+    pub height: usize,
+}
+
+impl Default for GameConfig {
+    fn default() -> GameConfig {
+        GameConfig {
+            level: 1,
+            seed: 0,
+            randomizer: Default::default(),
+            rotation_system: rotation_system::srs(),
+            goal: Goal::Endless,
+            garbage: Default::default(),
+            width: field::DEFAULT_WIDTH,
+            height: field::DEFAULT_HEIGHT,
+        }
+    }
+}
+
+// 出現時に押されているキー(IRS/IHS)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InitialActions {
+    pub rotation: Option<Rotation>,
+    pub hold: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Game {
-    pub field: FieldSize,
+    pub field: Field,
     pub pos: Position,
     pub tetromino: Tetromino,
     pub hold_tetromino: Option<Tetromino>,
     pub held: bool,
+    #[serde(skip)]
+    pub initial_actions: InitialActions,
     pub next_tetrominoes: VecDeque<Tetromino>,
+    pub rng: ChaCha8Rng,
+    pub randomizer: AnyRandomizer,
+    #[serde(with = "rotation_system::as_def")]
+    pub rotation_system: &'static RotationSystem,
     pub score: usize,
+    pub level: usize,
+    pub start_level: usize,
+    pub lines: usize,
+    pub back_to_back: bool,
+    pub combo: Option<usize>,
+    pub last_rotation: Option<(Rotation, usize)>, // 直前の操作が回転なら回転方向と使用したキックの番号
+    pub lock_delay: LockDelay,
+    pub statistics: Statistics,
+    pub goal: Goal,
+    pub elapsed: Duration, // プレイ時間
+    pub garbage: Garbage,
+    pub seed: u64,
+    #[serde(skip)]
+    pub events: Vec<GameEvent>, // まだ取り出されていないイベント
 }
 
 impl Game {
-    pub fn new() -> Game {
+    pub fn new(config: &GameConfig) -> Game {
+        let field = Field::new(config.width, config.height);
         let mut game = Game {
-            field: [
-                [0, W, W, W, 0, 0, 0, 0, 0, 0, W, W, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, W, W, W, W, W, W, W, W, W, W, W, 0],
-                [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
-            ],
-            pos: Position::init(),
+            pos: Position::init(&field),
+            field,
             tetromino: Default::default(),
             hold_tetromino: None,
             held: false,
-            next_tetrominoes: gen_tetromino_7().into(),
+            initial_actions: Default::default(),
+            next_tetrominoes: VecDeque::new(),
+            rng: ChaCha8Rng::seed_from_u64(config.seed),
+            randomizer: config.randomizer.create(),
+            rotation_system: config.rotation_system,
             score: 0,
+            level: config.level.max(1),
+            start_level: config.level.max(1),
+            lines: 0,
+            back_to_back: false,
+            combo: None,
+            last_rotation: None,
+            lock_delay: Default::default(),
+            statistics: Default::default(),
+            goal: config.goal,
+            elapsed: Duration::ZERO,
+            garbage: Garbage::new(config.seed, config.garbage),
+            seed: config.seed,
+            events: Vec::new(),
         };
+        fill_next_tetrominoes(&mut game);
+        refill_garbage(&mut game).ok();
         spawn_tetromino(&mut game).ok();
         game
     }
-}
 
-pub fn is_collision(field: &FieldSize, pos: &Position, tetromino: &Tetromino) -> bool {
-    let shape = tetromino.get_shape();
-    for y in 0..4 {
-        for x in 0..4 {
-            if y + pos.y >= FIELD_HEIGHT || x + pos.x >= FIELD_WIDTH {
-                return true;
-            }
-            if field[y + pos.y][x + pos.x] != cell::NONE && shape[y][x] != cell::NONE {
-                return true;
-            }
+    // 読み込んだテトリミノは回転法則を持たないので、ゲームのものを設定し直す
+    pub fn relink_rotation_system(&mut self) {
+        let rotation_system = self.rotation_system;
+        self.tetromino = self.tetromino.with_rotation_system(rotation_system);
+        self.hold_tetromino = self
+            .hold_tetromino
+            .map(|tetromino| tetromino.with_rotation_system(rotation_system));
+        for tetromino in self.next_tetrominoes.iter_mut() {
+            *tetromino = tetromino.with_rotation_system(rotation_system);
         }
     }
-    false
 }
 
-pub fn hard_drop_pos(field: &FieldSize, pos: &Position, tetromino: &Tetromino) -> Position {
+pub fn is_collision(field: &Field, pos: &Position, tetromino: &Tetromino) -> bool {
+    field.is_collision(pos.x, pos.y, &tetromino.shape_mask())
+}
+
+pub fn hard_drop_pos(field: &Field, pos: &Position, tetromino: &Tetromino) -> Position {
+    let mask = tetromino.shape_mask();
     let mut pos = *pos;
-    while {
-        let new_pos = Position {
-            x: pos.x,
-            y: pos.y + 1,
-        };
-        !is_collision(field, &new_pos, tetromino)
-    } {
+    while !field.is_collision(pos.x, pos.y + 1, &mask) {
         pos.y += 1;
     }
     pos
@@ -116,112 +199,704 @@ pub fn fix_tetromino(
         ..
     }: &mut Game,
 ) {
+    put_tetromino(field, pos, tetromino);
+}
+
+// フィールドにテトリミノのブロックを書き込む
+pub fn put_tetromino(field: &mut Field, pos: &Position, tetromino: &Tetromino) {
     let shape = tetromino.get_shape();
-    for y in 0..4 {
-        for x in 0..4 {
-            field[y + pos.y][x + pos.x] |= shape[y][x];
+    for (y, line) in shape.iter().enumerate() {
+        for (x, &kind) in line.iter().enumerate() {
+            if kind != cell::NONE {
+                field.set(x + pos.x, y + pos.y, kind);
+            }
         }
     }
 }
 
-pub fn erase_line(field: &mut FieldSize) -> usize {
-    let mut count = 0;
-    for y in 1..FIELD_HEIGHT - 2 {
-        let mut can_erase = true;
-        for x in 2..FIELD_WIDTH - 2 {
-            if field[y][x] == 0 {
-                can_erase = false;
-                break;
-            }
-        }
-        if can_erase {
-            count += 1;
-            for y2 in (2..=y).rev() {
-                field[y2] = field[y2 - 1];
-            }
+// 消した段(消える前の位置)を返す
+pub fn erase_line(field: &mut Field) -> Vec<usize> {
+    let mut rows = Vec::new();
+    for y in field.rows() {
+        if field.is_full_line(y) {
+            rows.push(y);
+            field.remove_line(y);
         }
     }
-    count
+    rows
 }
 
-fn wall_kick(game: &Game, tetromino: &Tetromino, offsets: &WallKickOffsets) -> Option<Position> {
-    for (dx, dy) in offsets {
+fn wall_kick(
+    field: &Field,
+    pos: &Position,
+    tetromino: &Tetromino,
+    offsets: WallKickOffsets,
+) -> Option<(usize, Position)> {
+    for (i, (dx, dy)) in offsets.iter().enumerate() {
         let new_pos = Position {
-            x: game.pos.x.checked_add_signed(*dx).unwrap_or(game.pos.x),
-            y: game.pos.y.checked_add_signed(*dy).unwrap_or(game.pos.y),
+            x: pos.x.checked_add_signed(*dx).unwrap_or(pos.x),
+            y: pos.y.checked_add_signed(*dy).unwrap_or(pos.y),
         };
-        if is_collision(&game.field, &new_pos, &tetromino) {
+        if is_collision(field, &new_pos, tetromino) {
             continue;
         }
-        return Some(new_pos);
+        return Some((i, new_pos));
     }
     None
 }
 
-pub fn rotate_left(game: &mut Game) {
-    let rotated = game.tetromino.rotate_left();
-    let offsets = game.tetromino.rotate_left_wall_kick_offsets();
-    if let Some(new_pos) = wall_kick(game, &rotated, &offsets) {
-        game.pos = new_pos;
-        game.tetromino = rotated;
-    }
+// 壁蹴りを含めて回転できれば、回転後のテトリミノと位置、使用したキックの番号を返す
+pub fn try_rotate(
+    field: &Field,
+    pos: &Position,
+    tetromino: &Tetromino,
+    rotation: Rotation,
+) -> Option<(Tetromino, Position, usize)> {
+    let rotated = tetromino.rotate(rotation);
+    let offsets = tetromino.wall_kick_offsets(rotation);
+    let (kick, new_pos) = wall_kick(field, pos, &rotated, offsets)?;
+    Some((rotated, new_pos, kick))
 }
 
-pub fn rotate_right(game: &mut Game) {
-    let rotated = game.tetromino.rotate_right();
-    let offsets = game.tetromino.rotate_right_wall_kick_offsets();
-    if let Some(new_pos) = wall_kick(game, &rotated, &offsets) {
+pub fn rotate(game: &mut Game, rotation: Rotation) {
+    if let Some((rotated, new_pos, kick)) =
+        try_rotate(&game.field, &game.pos, &game.tetromino, rotation)
+    {
         game.pos = new_pos;
         game.tetromino = rotated;
+        game.last_rotation = Some((rotation, kick));
+        reset_lock_delay(game);
     }
 }
 
 pub fn move_tetromino(game: &mut Game, new_pos: Position) {
-    if !is_collision(&game.field, &new_pos, &game.tetromino) {
+    if new_pos != game.pos && !is_collision(&game.field, &new_pos, &game.tetromino) {
         game.pos = new_pos;
+        game.last_rotation = None;
+        reset_lock_delay(game);
+    }
+}
+
+// 横にcountマス移動する(ぶつかったらそこで止まる)
+pub fn shift_tetromino(game: &mut Game, dx: isize, count: usize) {
+    for _ in 0..count {
+        let new_pos = Position {
+            x: game.pos.x.checked_add_signed(dx).unwrap_or(game.pos.x),
+            y: game.pos.y,
+        };
+        if new_pos == game.pos || is_collision(&game.field, &new_pos, &game.tetromino) {
+            break;
+        }
+        move_tetromino(game, new_pos);
+    }
+}
+
+pub fn is_grounded(game: &Game) -> bool {
+    let new_pos = Position {
+        x: game.pos.x,
+        y: game.pos.y + 1,
+    };
+    is_collision(&game.field, &new_pos, &game.tetromino)
+}
+
+fn reset_lock_delay(game: &mut Game) {
+    let grounded = is_grounded(game);
+    game.lock_delay.on_moved(game.pos.y, grounded);
+}
+
+// 接地中ならロックタイマーを進め、固定すべきときにtrueを返す
+pub fn advance_lock_delay(game: &mut Game, elapsed: Duration) -> bool {
+    if !is_grounded(game) {
+        return false;
     }
+    game.lock_delay.advance(elapsed);
+    game.lock_delay.is_expired()
+}
+
+// 最大rows段まで落下させる
+pub fn drop_tetromino(game: &mut Game, rows: usize) {
+    for _ in 0..rows {
+        if is_grounded(game) {
+            break;
+        }
+        let new_pos = Position {
+            x: game.pos.x,
+            y: game.pos.y + 1,
+        };
+        move_tetromino(game, new_pos);
+    }
+}
+
+// 最大rows段まで落下させ、落下した段数に応じて得点を加える
+pub fn soft_drop(game: &mut Game, rows: usize) {
+    let y = game.pos.y;
+    drop_tetromino(game, rows);
+    game.score += (game.pos.y - y) * score::SOFT_DROP_POINTS;
+}
+
+// 着地位置まで落下させる(固定はしない)
+pub fn sonic_drop(game: &mut Game) {
+    soft_drop(game, usize::MAX);
 }
 
 pub fn hard_drop(game: &mut Game) {
     let pos = hard_drop_pos(&game.field, &game.pos, &game.tetromino);
+    game.score += (pos.y - game.pos.y) * score::HARD_DROP_POINTS;
     move_tetromino(game, pos);
 }
 
-pub fn hold(game: &mut Game) {
+// 入れ替えたテトリミノが出現位置で重なればブロックアウト
+pub fn hold(game: &mut Game) -> Result<(), GameOverReason> {
     if game.held {
-        return;
+        return Ok(());
     }
-    if let Some(mut hold) = game.hold_tetromino {
-        std::mem::swap(&mut hold, &mut game.tetromino);
-        game.hold_tetromino = Some(hold);
-        game.pos = Position::init();
+    game.held = true;
+    let kind = game.tetromino.kind();
+    emit(game, GameEvent::Hold { kind });
+
+    // ホールドしたテトリミノは出現時の向きに戻す
+    let current = Tetromino::new(kind, game.rotation_system);
+    match game.hold_tetromino.replace(current) {
+        Some(hold) => {
+            game.tetromino = hold;
+            reset_spawn_state(game);
+        }
+        None => spawn_next_tetromino(game),
+    }
+
+    if is_collision(&game.field, &game.pos, &game.tetromino) {
+        Err(GameOverReason::BlockOut)
     } else {
-        game.hold_tetromino = Some(game.tetromino);
-        spawn_tetromino(game).ok();
+        Ok(())
     }
+}
 
-    game.held = true;
+// 3コーナールールによるTスピン判定
+// 回転法則によって中心の位置が違うので、形状から中心と凸の向きを求める
+pub fn detect_t_spin(game: &Game) -> Option<TSpin> {
+    t_spin(&game.field, &game.pos, &game.tetromino, game.last_rotation)
+}
+
+// last_rotation: 直前の操作が回転なら回転方向と使用したキックの番号
+pub fn t_spin(
+    field: &Field,
+    pos: &Position,
+    tetromino: &Tetromino,
+    last_rotation: Option<(Rotation, usize)>,
+) -> Option<TSpin> {
+    let (rotation, kick) = last_rotation?;
+    if tetromino.kind() != Kind::T {
+        return None;
+    }
+
+    let shape = tetromino.get_shape();
+    let in_shape = |x: isize, y: isize| {
+        (0..4).contains(&x) && (0..4).contains(&y) && shape[y as usize][x as usize] != cell::NONE
+    };
+    let directions = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+    let (cx, cy) = (0..4)
+        .flat_map(|y| (0..4).map(move |x| (x, y)))
+        .find(|&(x, y)| {
+            in_shape(x, y)
+                && directions
+                    .iter()
+                    .filter(|(dx, dy)| in_shape(x + dx, y + dy))
+                    .count()
+                    == 3
+        })?;
+    let (nx, ny) = *directions
+        .iter()
+        .find(|(dx, dy)| !in_shape(cx - dx, cy - dy))?;
+
+    let is_filled = |dx: isize, dy: isize| {
+        let x = (pos.x as isize + cx + dx) as usize;
+        let y = (pos.y as isize + cy + dy) as usize;
+        field[y][x] != cell::NONE
+    };
+    let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+    if corners
+        .iter()
+        .filter(|(dx, dy)| is_filled(*dx, *dy))
+        .count()
+        < 3
+    {
+        return None;
+    }
+
+    // 凸側の2マス
+    let front = [
+        (nx - ny.abs(), ny - nx.abs()),
+        (nx + ny.abs(), ny + nx.abs()),
+    ];
+    if front.iter().all(|(dx, dy)| is_filled(*dx, *dy)) || is_tst_kick(rotation, kick) {
+        Some(TSpin::Full)
+    } else {
+        Some(TSpin::Mini)
+    }
+}
+
+// 最後のキック(TSTキック)で入った場合はミニにならない
+pub fn is_tst_kick(rotation: Rotation, kick: usize) -> bool {
+    rotation != Rotation::Half && kick == 4
+}
+
+pub fn is_perfect_clear(field: &Field) -> bool {
+    field.rows().all(|y| field.is_empty_line(y))
+}
+
+// Back-to-Backが成立したらtrue
+fn add_clear_score(
+    game: &mut Game,
+    lines: usize,
+    t_spin: Option<TSpin>,
+    perfect_clear: bool,
+) -> bool {
+    let difficult = score::is_difficult(lines, t_spin);
+    let back_to_back = difficult && game.back_to_back;
+
+    let mut points = score::action_points(lines, t_spin);
+    if back_to_back {
+        points = points * 3 / 2;
+    }
+    if lines > 0 {
+        let combo = game.combo.map_or(0, |combo| combo + 1);
+        points += score::COMBO_POINTS * combo;
+        game.combo = Some(combo);
+        game.back_to_back = difficult;
+    } else {
+        game.combo = None;
+    }
+    if perfect_clear {
+        points += score::perfect_clear_points(lines, back_to_back);
+    }
+
+    game.score += points * game.level;
+    back_to_back
+}
+
+// 固定しようとしているテトリミノが可視領域より上に収まっているか
+fn is_lock_out(game: &Game) -> bool {
+    let shape = game.tetromino.get_shape();
+    let buffer_height = game.field.buffer_height();
+    (0..4).all(|y| shape[y].iter().all(|&c| c == cell::NONE) || game.pos.y + y < buffer_height)
+}
+
+// バッファ領域の最上段までブロックが積み上がったか
+pub fn is_top_out(field: &Field) -> bool {
+    !field.is_empty_line(0)
 }
 
-pub fn landing(game: &mut Game) -> Result<(), ()> {
+// 下からせり上がりをrows段差し込む
+pub fn add_garbage(game: &mut Game, rows: usize) -> Result<(), GameOverReason> {
+    if rows == 0 {
+        return Ok(());
+    }
+    emit(game, GameEvent::GarbageAdded { rows });
+    for _ in 0..rows {
+        let line = game.garbage.next_line(&game.field);
+        if !game.field.push_line(&line) {
+            return Err(GameOverReason::TopOut);
+        }
+    }
+    // 操作中のテトリミノに重なったら一緒に押し上げる
+    while is_collision(&game.field, &game.pos, &game.tetromino) && game.pos.y > 0 {
+        game.pos.y -= 1;
+    }
+    Ok(())
+}
+
+// 掘りモードでは残りの段数を上限にせり上がりを補充する
+fn refill_garbage(game: &mut Game) -> Result<(), GameOverReason> {
+    let Goal::Garbage(lines) = game.goal else {
+        return Ok(());
+    };
+    let target = lines
+        .saturating_sub(game.garbage.cleared)
+        .min(DIG_LINES)
+        .min(game.field.visible_height() / 2);
+    let rows = target.saturating_sub(garbage::count_lines(&game.field));
+    add_garbage(game, rows)
+}
+
+pub fn is_goal_reached(game: &Game) -> bool {
+    match game.goal {
+        Goal::Endless => false,
+        Goal::Lines(lines) => game.lines >= lines,
+        Goal::Time(limit) => game.elapsed >= limit,
+        Goal::Garbage(lines) => game.garbage.cleared >= lines,
+    }
+}
+
+pub fn landing(game: &mut Game) -> Result<(), GameOverReason> {
+    let result = lock_and_spawn(game);
+    if let Err(reason) = result {
+        emit(game, GameEvent::GameOver(reason));
+    }
+    result
+}
+
+// 統計に反映してイベントを積む
+fn emit(game: &mut Game, event: GameEvent) {
+    game.statistics.record(&event);
+    game.events.push(event);
+}
+
+pub fn take_events(game: &mut Game) -> Vec<GameEvent> {
+    std::mem::take(&mut game.events)
+}
+
+fn lock_and_spawn(game: &mut Game) -> Result<(), GameOverReason> {
+    let t_spin = detect_t_spin(game);
+    let lock_out = is_lock_out(game);
     fix_tetromino(game);
-    let count = erase_line(&mut game.field);
-    game.score += SCORE_TABLE[count];
-    spawn_tetromino(game)?;
+    let kind = game.tetromino.kind();
+    emit(game, GameEvent::PieceLocked { kind, t_spin });
+    if lock_out {
+        return Err(GameOverReason::LockOut);
+    }
+    game.garbage.cleared += garbage::count_full_lines(&game.field);
+    let rows = erase_line(&mut game.field);
+    let count = rows.len();
+    let perfect_clear = count > 0 && is_perfect_clear(&game.field);
+    let combo = game.combo;
+    let back_to_back = add_clear_score(game, count, t_spin, perfect_clear);
+    if count > 0 {
+        let kind = ClearKind::new(count, t_spin);
+        emit(
+            game,
+            GameEvent::LinesCleared {
+                rows,
+                kind,
+                back_to_back,
+                perfect_clear,
+            },
+        );
+    }
+    if game.combo != combo {
+        let combo = game.combo;
+        emit(game, GameEvent::ComboChanged { combo });
+    }
+    game.lines += count;
+    // 目標のあるモードではレベルを固定する
+    let level = game.level;
+    if game.goal == Goal::Endless {
+        game.level = gravity::level_for_lines(game.start_level, game.lines);
+    }
+    if game.level > level {
+        let level = game.level;
+        emit(game, GameEvent::LevelUp { level });
+    }
+    // 目標を達成したら次のテトリミノは出さない
+    if is_goal_reached(game) {
+        return Ok(());
+    }
+    refill_garbage(game)?;
+    if is_top_out(&game.field) {
+        return Err(GameOverReason::TopOut);
+    }
     game.held = false;
-    Ok(())
+    spawn_tetromino(game)
+}
+
+fn fill_next_tetrominoes(game: &mut Game) {
+    while game.next_tetrominoes.len() < NEXT_TETROMINOES_SIZE {
+        let kind = game.randomizer.next(&mut game.rng);
+        game.next_tetrominoes
+            .push_back(Tetromino::new(kind, game.rotation_system));
+    }
+}
+
+// 回転法則ごとの補正を加えた出現位置
+pub fn spawn_position(field: &Field, tetromino: &Tetromino) -> Position {
+    let pos = Position::init(field);
+    let (dx, dy) = tetromino.spawn_offset();
+    Position {
+        x: pos.x.checked_add_signed(dx).unwrap_or(pos.x),
+        y: pos.y.checked_add_signed(dy).unwrap_or(pos.y),
+    }
+}
+
+fn reset_spawn_state(game: &mut Game) {
+    game.pos = spawn_position(&game.field, &game.tetromino);
+    game.last_rotation = None;
+    game.lock_delay = LockDelay::new(game.pos.y);
+    let kind = game.tetromino.kind();
+    emit(game, GameEvent::PieceSpawned { kind });
 }
 
-pub fn spawn_tetromino(game: &mut Game) -> Result<(), ()> {
-    game.pos = Position::init();
+fn spawn_next_tetromino(game: &mut Game) {
     game.tetromino = game.next_tetrominoes.pop_front().unwrap();
-    if game.next_tetrominoes.len() < NEXT_TETROMINOES_SIZE {
-        let mut next7: VecDeque<_> = gen_tetromino_7().into();
-        game.next_tetrominoes.append(&mut next7);
+    fill_next_tetrominoes(game);
+    reset_spawn_state(game);
+}
+
+pub fn spawn_tetromino(game: &mut Game) -> Result<(), GameOverReason> {
+    spawn_next_tetromino(game);
+
+    // IHS/IRS: 出現時にホールド・回転キーが押されていれば先に処理する
+    let initial_actions = game.initial_actions;
+    if initial_actions.hold && !game.held {
+        hold(game)?;
+    }
+    if let Some(rotation) = initial_actions.rotation {
+        rotate(game, rotation);
+        game.last_rotation = None;
+        game.lock_delay = LockDelay::new(game.pos.y);
     }
+
     if is_collision(&game.field, &game.pos, &game.tetromino) {
-        Err(())
+        Err(GameOverReason::BlockOut)
     } else {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::lock_delay::LOCK_DELAY;
+    use super::tetromino::RotateState;
+    use super::*;
+
+    const WAIT: Duration = Duration::from_millis(200);
+
+    // 操作中のテトリミノを着地させて少し待つ
+    fn grounded_game() -> Game {
+        let mut game = Game::new(&GameConfig::default());
+        let pos = hard_drop_pos(&game.field, &game.pos, &game.tetromino);
+        move_tetromino(&mut game, pos);
+        assert!(is_grounded(&game));
+        assert!(!advance_lock_delay(&mut game, WAIT));
+        game
+    }
+
+    // 一番下の段を、操作中のテトリミノを落とすとちょうど埋まるようにして固定する
+    fn clear_bottom_line(game: &mut Game) -> Result<(), GameOverReason> {
+        let pos = hard_drop_pos(&game.field, &game.pos, &game.tetromino);
+        let mut landed = game.field.clone();
+        put_tetromino(&mut landed, &pos, &game.tetromino);
+        let bottom = game.field.floor() - 1;
+        for x in game.field.columns() {
+            if landed[bottom][x] == cell::NONE {
+                game.field.set(x, bottom, cell::GARBAGE);
+            }
+        }
+        move_tetromino(game, pos);
+        landing(game)
+    }
+
+    #[test]
+    fn level_up_from_start_level() {
+        for start_level in [1, 5] {
+            let mut game = Game::new(&GameConfig {
+                level: start_level,
+                ..Default::default()
+            });
+            game.lines = 9;
+            clear_bottom_line(&mut game).unwrap();
+            assert_eq!(game.lines, 10);
+            assert_eq!(game.level, start_level + 1);
+            assert!(game.events.contains(&GameEvent::LevelUp {
+                level: start_level + 1
+            }));
+        }
+    }
+
+    // Tの中心を(x, y)に置いたとき、中心から見て(dx, dy)の角を埋める
+    fn t_spin_field(x: usize, y: usize, corners: &[(isize, isize)]) -> Field {
+        let mut field = Field::default();
+        for (dx, dy) in corners {
+            let cx = x.checked_add_signed(*dx).unwrap();
+            let cy = y.checked_add_signed(*dy).unwrap();
+            field.set(cx, cy, cell::GARBAGE);
+        }
+        field
+    }
+
+    // 下向き(2)のTを一番下の段の1マスの穴に差し込んだ形(凸側は下の2つの角)
+    fn t_spin_slot(
+        corners: &[(isize, isize)],
+        last_rotation: Option<(Rotation, usize)>,
+    ) -> Option<TSpin> {
+        let tetromino =
+            Tetromino::with_rotate_state(Kind::T, RotateState::_2, rotation_system::srs());
+        let field = Field::default();
+        let (x, y) = (field.columns().start + 4, field.floor() - 2);
+        let field = t_spin_field(x, y, corners);
+        // SRSの2ではTの中心が4x4の(1, 1)
+        let pos = Position { x: x - 1, y: y - 1 };
+        assert!(!is_collision(&field, &pos, &tetromino));
+        t_spin(&field, &pos, &tetromino, last_rotation)
+    }
+
+    const BACK: [(isize, isize); 2] = [(-1, -1), (1, -1)];
+    const FRONT: [(isize, isize); 2] = [(-1, 1), (1, 1)];
+
+    #[test]
+    fn t_spin_full_with_both_front_corners() {
+        let corners = [BACK[0], FRONT[0], FRONT[1]];
+        let rotation = Some((Rotation::Right, 0));
+        assert_eq!(t_spin_slot(&corners, rotation), Some(TSpin::Full));
+        assert_eq!(
+            t_spin_slot(&[BACK, FRONT].concat(), rotation),
+            Some(TSpin::Full)
+        );
+    }
+
+    #[test]
+    fn t_spin_mini_with_one_front_corner() {
+        let corners = [BACK[0], BACK[1], FRONT[0]];
+        assert_eq!(
+            t_spin_slot(&corners, Some((Rotation::Left, 0))),
+            Some(TSpin::Mini)
+        );
+        // TSTキックで入ったらミニにならない(180度回転は除く)
+        assert_eq!(
+            t_spin_slot(&corners, Some((Rotation::Left, 4))),
+            Some(TSpin::Full)
+        );
+        assert_eq!(
+            t_spin_slot(&corners, Some((Rotation::Half, 4))),
+            Some(TSpin::Mini)
+        );
+    }
+
+    #[test]
+    fn no_t_spin() {
+        // 角が2つしか埋まっていない
+        assert_eq!(t_spin_slot(&FRONT, Some((Rotation::Right, 0))), None);
+        // 最後の操作が回転でない
+        assert_eq!(t_spin_slot(&[BACK, FRONT].concat(), None), None);
+        // T以外
+        let tetromino = Tetromino::new(Kind::S, rotation_system::srs());
+        let field = t_spin_field(6, 30, &[BACK, FRONT].concat());
+        let pos = Position { x: 5, y: 29 };
+        assert_eq!(
+            t_spin(&field, &pos, &tetromino, Some((Rotation::Right, 0))),
+            None
+        );
+    }
+
+    #[test]
+    fn clear_score_with_level() {
+        let mut game = Game::new(&GameConfig {
+            level: 3,
+            ..Default::default()
+        });
+        assert!(!add_clear_score(&mut game, 2, None, false));
+        assert_eq!(game.score, 300 * 3);
+    }
+
+    #[test]
+    fn back_to_back_bonus() {
+        let mut game = Game::new(&GameConfig::default());
+        assert!(!add_clear_score(&mut game, 4, None, false));
+        assert_eq!(game.score, 800);
+        assert!(game.back_to_back);
+
+        // 2回目の難しい消去は1.5倍(コンボも1つ付く)
+        game.score = 0;
+        assert!(add_clear_score(&mut game, 2, Some(TSpin::Full), false));
+        assert_eq!(game.score, 1200 * 3 / 2 + score::COMBO_POINTS);
+
+        // ラインを消さないTスピンでは途切れない
+        game.score = 0;
+        assert!(!add_clear_score(&mut game, 0, Some(TSpin::Full), false));
+        assert_eq!(game.score, 400);
+        assert!(game.back_to_back);
+
+        // 難しくない消去で途切れる
+        assert!(!add_clear_score(&mut game, 1, None, false));
+        assert!(!game.back_to_back);
+        game.score = 0;
+        assert!(!add_clear_score(&mut game, 4, None, false));
+        assert_eq!(game.score, 800 + score::COMBO_POINTS);
+    }
+
+    #[test]
+    fn combo_bonus() {
+        let mut game = Game::new(&GameConfig::default());
+        for combo in 0..4 {
+            game.score = 0;
+            add_clear_score(&mut game, 1, None, false);
+            assert_eq!(game.combo, Some(combo));
+            assert_eq!(game.score, 100 + score::COMBO_POINTS * combo);
+        }
+        // 消さずに固定したら途切れる
+        add_clear_score(&mut game, 0, None, false);
+        assert_eq!(game.combo, None);
+        game.score = 0;
+        add_clear_score(&mut game, 1, None, false);
+        assert_eq!(game.score, 100);
+    }
+
+    #[test]
+    fn perfect_clear_bonus() {
+        let mut game = Game::new(&GameConfig::default());
+        add_clear_score(&mut game, 4, None, true);
+        assert_eq!(game.score, 800 + 2000);
+
+        game.score = 0;
+        game.combo = None;
+        assert!(add_clear_score(&mut game, 4, None, true));
+        assert_eq!(game.score, 800 * 3 / 2 + 3200);
+    }
+
+    #[test]
+    fn perfect_clear_on_lock() {
+        // 横向きのIで一番下の段だけを消す
+        let mut game = Game::new(&GameConfig::default());
+        game.tetromino = Tetromino::new(Kind::I, game.rotation_system);
+        game.pos = spawn_position(&game.field, &game.tetromino);
+        clear_bottom_line(&mut game).unwrap();
+        assert!(game.events.contains(&GameEvent::LinesCleared {
+            rows: vec![game.field.floor() - 1],
+            kind: ClearKind::Single,
+            back_to_back: false,
+            perfect_clear: true,
+        }));
+        assert_eq!(game.score, 100 + 800);
+    }
+
+    #[test]
+    fn drop_points() {
+        let mut game = Game::new(&GameConfig::default());
+        soft_drop(&mut game, 3);
+        assert_eq!(game.score, 3 * score::SOFT_DROP_POINTS);
+
+        let y = game.pos.y;
+        let bottom = hard_drop_pos(&game.field, &game.pos, &game.tetromino).y;
+        hard_drop(&mut game);
+        assert_eq!(
+            game.score,
+            3 * score::SOFT_DROP_POINTS + (bottom - y) * score::HARD_DROP_POINTS
+        );
+    }
+
+    #[test]
+    fn lock_delay_resets_on_move() {
+        let mut game = grounded_game();
+        shift_tetromino(&mut game, 1, 1);
+        assert_eq!(game.lock_delay.remaining(), LOCK_DELAY);
+    }
+
+    #[test]
+    fn lock_delay_keeps_running_without_move() {
+        let mut game = grounded_game();
+        let pos = game.pos;
+        move_tetromino(&mut game, pos);
+        assert_eq!(game.lock_delay.remaining(), LOCK_DELAY - WAIT);
+
+        // 壁に押し付けても動かなければリセットしない
+        let width = game.field.width();
+        shift_tetromino(&mut game, -1, width);
+        advance_lock_delay(&mut game, WAIT);
+        shift_tetromino(&mut game, -1, 1);
+        assert_eq!(game.lock_delay.remaining(), LOCK_DELAY - WAIT);
+    }
+
+    #[test]
+    fn lock_delay_expires_on_ground() {
+        let mut game = grounded_game();
+        assert!(advance_lock_delay(&mut game, LOCK_DELAY - WAIT));
+    }
+}
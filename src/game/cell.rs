@@ -10,3 +10,4 @@ pub const Z: Kind = 6;
 pub const J: Kind = 7;
 pub const L: Kind = 8;
 pub const T: Kind = 9;
+pub const GARBAGE: Kind = 10;
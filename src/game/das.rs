@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+}
+
+impl Direction {
+    pub fn dx(self) -> isize {
+        match self {
+            Direction::Left => -1,
+            Direction::Right => 1,
+        }
+    }
+}
+
+// 横移動のオートリピート(DAS/ARR)
+// ARRが0のときは壁まで一気に移動する
+#[derive(Clone, Debug)]
+pub struct AutoShift {
+    das: Duration,
+    arr: Duration,
+    held: Vec<Direction>, // 押下中の方向(最後に押したものが有効)
+    elapsed: Duration,
+    next_shift: Duration,
+}
+
+impl AutoShift {
+    pub fn new(das: Duration, arr: Duration) -> AutoShift {
+        AutoShift {
+            das,
+            arr,
+            held: Vec::new(),
+            elapsed: Duration::ZERO,
+            next_shift: das,
+        }
+    }
+
+    pub fn direction(&self) -> Option<Direction> {
+        self.held.last().copied()
+    }
+
+    pub fn press(&mut self, direction: Direction) {
+        self.held.retain(|&d| d != direction);
+        self.held.push(direction);
+        self.restart();
+    }
+
+    pub fn release(&mut self, direction: Direction) {
+        let active = self.direction() == Some(direction);
+        self.held.retain(|&d| d != direction);
+        // 反対側のキーがまだ押されていればそちらのDASをやり直す
+        if active {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.elapsed = Duration::ZERO;
+        self.next_shift = self.das;
+    }
+
+    // 時間を進めて、その間に移動すべきマス数を返す
+    pub fn update(&mut self, elapsed: Duration) -> usize {
+        if self.direction().is_none() {
+            return 0;
+        }
+        self.elapsed += elapsed;
+        if self.elapsed < self.next_shift {
+            return 0;
+        }
+        if self.arr.is_zero() {
+            return usize::MAX;
+        }
+
+        let mut count = 0;
+        while self.elapsed >= self.next_shift {
+            count += 1;
+            self.next_shift += self.arr;
+        }
+        count
+    }
+}
@@ -0,0 +1,311 @@
+use super::das::{AutoShift, Direction};
+use super::event::GameEvent;
+use super::gravity::{self, FRAME};
+use super::tetromino::Rotation;
+use super::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct InputConfig {
+    pub das: Duration,
+    pub arr: Duration,
+    pub soft_drop_factor: f64, // ソフトドロップ中は落下速度をこの倍率にする
+}
+
+impl Default for InputConfig {
+    fn default() -> InputConfig {
+        InputConfig {
+            das: Duration::from_millis(167),
+            arr: Duration::from_millis(33),
+            soft_drop_factor: 20.0,
+        }
+    }
+}
+
+// キー割り当てを解決した操作
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Action {
+    Left,
+    Right,
+    SoftDrop,
+    HardDrop,
+    SonicDrop,
+    RotateLeft,
+    RotateRight,
+    Rotate180,
+    Hold,
+}
+
+impl Action {
+    fn direction(self) -> Option<Direction> {
+        match self {
+            Action::Left => Some(Direction::Left),
+            Action::Right => Some(Direction::Right),
+            _ => None,
+        }
+    }
+
+    fn rotation(self) -> Option<Rotation> {
+        match self {
+            Action::RotateLeft => Some(Rotation::Left),
+            Action::RotateRight => Some(Rotation::Right),
+            Action::Rotate180 => Some(Rotation::Half),
+            _ => None,
+        }
+    }
+}
+
+// 離したことが分からない場合は同じフレームで押して離す
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Input {
+    Press(Action),
+    Release(Action),
+}
+
+// 1フレームずつゲームを進める
+// 落下、ロックダウン、DASの時間はすべてフレーム単位で進むので、同じ入力列なら同じ結果になる
+#[derive(Clone, Debug)]
+pub struct Engine {
+    pub game: Game,
+    input: InputConfig,
+    auto_shift: AutoShift,
+    soft_dropping: bool,
+    auto_drop_in: Duration, // 次の自動落下まで
+    frame: u64,
+    finished: bool,
+}
+
+impl Engine {
+    pub fn new(game: Game, input: InputConfig) -> Engine {
+        let auto_drop_in = gravity::auto_drop(game.level).0;
+        Engine {
+            game,
+            input,
+            auto_shift: AutoShift::new(input.das, input.arr),
+            soft_dropping: false,
+            auto_drop_in,
+            frame: 0,
+            finished: false,
+        }
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // ゲームオーバーか目標達成で終了
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // このフレームで起きたイベントを返す
+    pub fn step(&mut self, inputs: &[Input]) -> Vec<GameEvent> {
+        if !self.finished {
+            self.advance(inputs);
+        }
+        take_events(&mut self.game)
+    }
+
+    fn advance(&mut self, inputs: &[Input]) {
+        self.frame += 1;
+        self.game.elapsed += FRAME;
+
+        for &input in inputs {
+            self.process_input(input);
+            if self.finished {
+                return;
+            }
+        }
+
+        if let Some(direction) = self.auto_shift.direction() {
+            let count = self.auto_shift.update(FRAME);
+            shift_tetromino(&mut self.game, direction.dx(), count);
+        }
+
+        // 端数を持ち越して落下間隔がフレームの倍数でなくてもずれないようにする
+        if self.auto_drop_in <= FRAME {
+            let (interval, rows) = if self.soft_dropping {
+                gravity::soft_drop(self.game.level, self.input.soft_drop_factor)
+            } else {
+                gravity::auto_drop(self.game.level)
+            };
+            if self.soft_dropping {
+                soft_drop(&mut self.game, rows);
+            } else {
+                drop_tetromino(&mut self.game, rows);
+            }
+            self.auto_drop_in = self.auto_drop_in + interval - FRAME;
+        } else {
+            self.auto_drop_in -= FRAME;
+        }
+
+        // 接地中ならロックタイマーを進め、時間切れで固定
+        if advance_lock_delay(&mut self.game, FRAME) {
+            self.land();
+            if self.finished {
+                return;
+            }
+        }
+
+        if is_goal_reached(&self.game) {
+            self.reach_goal();
+        }
+    }
+
+    fn reach_goal(&mut self) {
+        self.finished = true;
+        emit(&mut self.game, GameEvent::GoalReached);
+    }
+
+    fn game_over(&mut self, reason: GameOverReason) {
+        self.finished = true;
+        emit(&mut self.game, GameEvent::GameOver(reason));
+    }
+
+    // ゲームオーバーのイベントはlandingが出す
+    fn land(&mut self) {
+        if landing(&mut self.game).is_err() {
+            self.finished = true;
+        } else if is_goal_reached(&self.game) {
+            self.reach_goal();
+        } else {
+            self.auto_drop_in = gravity::auto_drop(self.game.level).0;
+        }
+    }
+
+    fn process_input(&mut self, input: Input) {
+        let game = &mut self.game;
+        match input {
+            Input::Press(action) => {
+                if let Some(direction) = action.direction() {
+                    self.auto_shift.press(direction);
+                    shift_tetromino(game, direction.dx(), 1);
+                }
+                // 押しっぱなしなら次の出現時にIRS/IHS
+                if let Some(rotation) = action.rotation() {
+                    game.initial_actions.rotation = Some(rotation);
+                    rotate(game, rotation);
+                }
+                match action {
+                    // 押している間は自動落下をソフトドロップの速度にする
+                    Action::SoftDrop => {
+                        self.soft_dropping = true;
+                        soft_drop(game, 1);
+                        self.auto_drop_in =
+                            gravity::soft_drop(game.level, self.input.soft_drop_factor).0;
+                    }
+                    Action::HardDrop => {
+                        hard_drop(game);
+                        self.land();
+                    }
+                    Action::SonicDrop => {
+                        sonic_drop(game);
+                    }
+                    Action::Hold => {
+                        game.initial_actions.hold = true;
+                        if let Err(reason) = hold(game) {
+                            self.game_over(reason);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            Input::Release(action) => {
+                if let Some(direction) = action.direction() {
+                    self.auto_shift.release(direction);
+                }
+                let rotation = action.rotation();
+                if rotation.is_some() && rotation == game.initial_actions.rotation {
+                    game.initial_actions.rotation = None;
+                }
+                match action {
+                    Action::SoftDrop => {
+                        self.soft_dropping = false;
+                        self.auto_drop_in = gravity::auto_drop(game.level).0;
+                    }
+                    Action::Hold => {
+                        game.initial_actions.hold = false;
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    // 押しっぱなしやDAS、ホールドも含む適当な入力列
+    fn inputs(seed: u64, frames: usize) -> Vec<Vec<Input>> {
+        const ACTIONS: [Action; 9] = [
+            Action::Left,
+            Action::Right,
+            Action::SoftDrop,
+            Action::HardDrop,
+            Action::SonicDrop,
+            Action::RotateLeft,
+            Action::RotateRight,
+            Action::Rotate180,
+            Action::Hold,
+        ];
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut pressed = Vec::new();
+        (0..frames)
+            .map(|_| {
+                let mut inputs = Vec::new();
+                if rng.gen_bool(0.2) {
+                    let action = ACTIONS[rng.gen_range(0..ACTIONS.len())];
+                    if !pressed.contains(&action) {
+                        pressed.push(action);
+                        inputs.push(Input::Press(action));
+                    }
+                }
+                if !pressed.is_empty() && rng.gen_bool(0.3) {
+                    let action = pressed.remove(rng.gen_range(0..pressed.len()));
+                    inputs.push(Input::Release(action));
+                }
+                inputs
+            })
+            .collect()
+    }
+
+    fn run(seed: u64, inputs: &[Vec<Input>]) -> (Engine, Vec<GameEvent>) {
+        let mut engine = Engine::new(
+            Game::new(&GameConfig {
+                seed,
+                ..Default::default()
+            }),
+            InputConfig::default(),
+        );
+        let mut events = Vec::new();
+        for inputs in inputs {
+            events.extend(engine.step(inputs));
+        }
+        (engine, events)
+    }
+
+    #[test]
+    fn same_inputs_give_same_game() {
+        for seed in 0..4 {
+            let inputs = inputs(seed, 6000);
+            let (a, a_events) = run(seed, &inputs);
+            let (b, b_events) = run(seed, &inputs);
+            assert!(a.game.statistics.pieces > 0);
+            assert_eq!(a.game.score, b.game.score);
+            assert_eq!(a.game.lines, b.game.lines);
+            assert_eq!(a.frame(), b.frame());
+            assert_eq!(a.game.field, b.game.field);
+            assert_eq!(a_events, b_events);
+        }
+    }
+
+    #[test]
+    fn different_inputs_give_different_game() {
+        let (a, _) = run(0, &inputs(0, 6000));
+        let (b, _) = run(0, &inputs(1, 6000));
+        assert_ne!(a.game.field, b.game.field);
+    }
+}
@@ -0,0 +1,78 @@
+use super::score::TSpin;
+use super::tetromino::Kind;
+use super::GameOverReason;
+
+// 消去の種類(Tスピンは消した段数も持つ)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClearKind {
+    Single,
+    Double,
+    Triple,
+    Tetris,
+    TSpinMini(usize),
+    TSpin(usize),
+}
+
+impl ClearKind {
+    pub fn new(lines: usize, t_spin: Option<TSpin>) -> ClearKind {
+        match (t_spin, lines) {
+            (Some(TSpin::Mini), _) => ClearKind::TSpinMini(lines),
+            (Some(TSpin::Full), _) => ClearKind::TSpin(lines),
+            (None, 1) => ClearKind::Single,
+            (None, 2) => ClearKind::Double,
+            (None, 3) => ClearKind::Triple,
+            (None, _) => ClearKind::Tetris,
+        }
+    }
+
+    pub fn name(self) -> String {
+        let lines = |lines: usize| match lines {
+            0 => "",
+            1 => "SINGLE",
+            2 => "DOUBLE",
+            _ => "TRIPLE",
+        };
+        match self {
+            ClearKind::Single => "SINGLE".to_string(),
+            ClearKind::Double => "DOUBLE".to_string(),
+            ClearKind::Triple => "TRIPLE".to_string(),
+            ClearKind::Tetris => "TETRIS".to_string(),
+            ClearKind::TSpinMini(n) => format!("T-SPIN MINI {}", lines(n)),
+            ClearKind::TSpin(n) => format!("T-SPIN {}", lines(n)),
+        }
+        .trim_end()
+        .to_string()
+    }
+}
+
+// ゲームの中で起きたこと(Game::eventsにたまり、Engine::stepが返す)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GameEvent {
+    PieceSpawned {
+        kind: Kind,
+    },
+    Hold {
+        kind: Kind, // ホールドに入れた種類
+    },
+    PieceLocked {
+        kind: Kind,
+        t_spin: Option<TSpin>, // ラインを消さなくても判定される
+    },
+    LinesCleared {
+        rows: Vec<usize>, // 消える前のフィールド上の段
+        kind: ClearKind,
+        back_to_back: bool,
+        perfect_clear: bool,
+    },
+    ComboChanged {
+        combo: Option<usize>,
+    },
+    LevelUp {
+        level: usize,
+    },
+    GarbageAdded {
+        rows: usize,
+    },
+    GameOver(GameOverReason),
+    GoalReached,
+}
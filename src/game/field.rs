@@ -0,0 +1,407 @@
+use super::cell;
+use super::tetromino::ShapeMask;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::ops::{Index, Range};
+
+pub const DEFAULT_WIDTH: usize = 10;
+pub const DEFAULT_HEIGHT: usize = 20;
+pub const MIN_WIDTH: usize = 4; // 横向きのIが入る幅
+pub const MIN_HEIGHT: usize = 4;
+pub const MIN_BUFFER_HEIGHT: usize = 20; // 低いフィールドでもこれだけは見えない領域を持つ
+pub const MAX_WIDTH: usize = Row::BITS as usize - BORDER * 2; // 番兵まで1段のビットに収める
+
+const BORDER: usize = 2; // 左右それぞれの壁+番兵
+const BOTTOM: usize = 2; // 床+番兵
+
+// 1段のブロックの有無をビットで表したもの(x列目が下からxビット目)
+pub type Row = u64;
+
+// 壁、床、番兵を含むフィールド
+// 左右に番兵1列と壁1列、下に床1段と番兵1段を付け、可視領域の上にバッファ領域を持つ
+// バッファ領域は可視領域と同じ高さで、MIN_BUFFER_HEIGHT段より低くはしない
+// 当たり判定と消去判定は段ごとのビット列で行い、マスの種類(色)は表示用に別に持つ
+#[derive(Clone, PartialEq, Eq)]
+pub struct Field {
+    width: usize,  // 番兵を含む横幅
+    height: usize, // 番兵を含む高さ
+    cells: Vec<cell::Kind>,
+    rows: Vec<Row>,
+}
+
+impl Field {
+    // width, heightは可視領域の大きさ
+    pub fn new(width: usize, height: usize) -> Field {
+        let width = width.clamp(MIN_WIDTH, MAX_WIDTH) + BORDER * 2;
+        let visible_height = height.max(MIN_HEIGHT);
+        let height = visible_height + buffer_height(visible_height) + BOTTOM;
+        let mut field = Field {
+            width,
+            height,
+            cells: vec![cell::NONE; width * height],
+            rows: vec![0; height],
+        };
+        for y in 0..height - 1 {
+            field.set(1, y, cell::WALL);
+            field.set(width - 2, y, cell::WALL);
+        }
+        for x in 1..width - 1 {
+            field.set(x, field.floor(), cell::WALL);
+        }
+        field
+    }
+
+    // 番兵を含む大きさ
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn playfield_width(&self) -> usize {
+        self.width - BORDER * 2
+    }
+
+    pub fn visible_height(&self) -> usize {
+        self.height - BOTTOM - self.buffer_height()
+    }
+
+    // 可視領域の上にある見えない領域
+    pub fn buffer_height(&self) -> usize {
+        let rows = self.height - BOTTOM;
+        if rows >= MIN_BUFFER_HEIGHT * 2 {
+            rows / 2
+        } else {
+            MIN_BUFFER_HEIGHT
+        }
+    }
+
+    // ブロックを置ける列
+    pub fn columns(&self) -> Range<usize> {
+        BORDER..self.width - BORDER
+    }
+
+    // ブロックを置ける段(バッファ領域を含む)
+    pub fn rows(&self) -> Range<usize> {
+        0..self.floor()
+    }
+
+    pub fn floor(&self) -> usize {
+        self.height - BOTTOM
+    }
+
+    // 範囲外ならNone
+    pub fn get(&self, x: usize, y: usize) -> Option<cell::Kind> {
+        if x < self.width && y < self.height {
+            Some(self[y][x])
+        } else {
+            None
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, kind: cell::Kind) {
+        self.cells[y * self.width + x] = kind;
+        if kind == cell::NONE {
+            self.rows[y] &= !(1 << x);
+        } else {
+            self.rows[y] |= 1 << x;
+        }
+    }
+
+    pub fn row(&self, y: usize) -> Row {
+        self.rows[y]
+    }
+
+    // ブロックを置ける列のビット
+    pub fn columns_mask(&self) -> Row {
+        let columns = self.columns();
+        (Row::MAX >> (Row::BITS as usize - columns.len())) << columns.start
+    }
+
+    // 4x4の範囲がフィールドからはみ出すか、ブロックと重なるか
+    pub fn is_collision(&self, x: usize, y: usize, mask: &ShapeMask) -> bool {
+        if x + 4 > self.width || y + 4 > self.height {
+            return true;
+        }
+        mask.iter()
+            .zip(&self.rows[y..y + 4])
+            .any(|(&shape, &row)| row & (shape << x) != 0)
+    }
+
+    pub fn empty_line(&self) -> Vec<cell::Kind> {
+        let mut line = vec![cell::NONE; self.width];
+        line[1] = cell::WALL;
+        line[self.width - 2] = cell::WALL;
+        line
+    }
+
+    pub fn is_empty_line(&self, y: usize) -> bool {
+        self.rows[y] & self.columns_mask() == 0
+    }
+
+    pub fn is_full_line(&self, y: usize) -> bool {
+        let columns = self.columns_mask();
+        self.rows[y] & columns == columns
+    }
+
+    // 段を取り除いて上の段を1段ずつ下げる
+    pub fn remove_line(&mut self, y: usize) {
+        self.cells.copy_within(0..y * self.width, self.width);
+        self.rows.copy_within(0..y, 1);
+        let line = self.empty_line();
+        self.set_line(0, &line);
+    }
+
+    fn set_line(&mut self, y: usize, line: &[cell::Kind]) {
+        self.cells[y * self.width..(y + 1) * self.width].copy_from_slice(line);
+        self.rows[y] = row_bits(line);
+    }
+
+    // 床の上に1段差し込んで全体を押し上げる
+    // 一番上の段にブロックがあってあふれた場合はfalse
+    pub fn push_line(&mut self, line: &[cell::Kind]) -> bool {
+        let overflow = !self.is_empty_line(0);
+        let bottom = self.floor() - 1;
+        self.cells
+            .copy_within(self.width..(bottom + 1) * self.width, 0);
+        self.rows.copy_within(1..=bottom, 0);
+        self.set_line(bottom, line);
+        !overflow
+    }
+
+    // 番兵を含む全ての段
+    pub fn lines(&self) -> impl Iterator<Item = &[cell::Kind]> {
+        self.cells.chunks(self.width)
+    }
+}
+
+impl Default for Field {
+    fn default() -> Field {
+        Field::new(DEFAULT_WIDTH, DEFAULT_HEIGHT)
+    }
+}
+
+impl Index<usize> for Field {
+    type Output = [cell::Kind];
+
+    fn index(&self, y: usize) -> &[cell::Kind] {
+        &self.cells[y * self.width..(y + 1) * self.width]
+    }
+}
+
+// 1段を1行の文字列で出す(空きは'.'、壁は'#'、ブロックは種類の番号)
+impl fmt::Debug for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines = self.lines().map(|line| {
+            line.iter()
+                .map(|&kind| match kind {
+                    cell::NONE => '.',
+                    cell::WALL => '#',
+                    _ => char::from_digit(kind as u32, 36).unwrap_or('?'),
+                })
+                .collect::<String>()
+        });
+        f.debug_list().entries(lines).finish()
+    }
+}
+
+fn buffer_height(visible_height: usize) -> usize {
+    visible_height.max(MIN_BUFFER_HEIGHT)
+}
+
+fn row_bits(line: &[cell::Kind]) -> Row {
+    line.iter()
+        .enumerate()
+        .filter(|(_, &c)| c != cell::NONE)
+        .fold(0, |row, (x, _)| row | 1 << x)
+}
+
+// 段の列として保存する(大きさは段の長さと数から決まる)
+impl Serialize for Field {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.lines())
+    }
+}
+
+impl<'de> Deserialize<'de> for Field {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Field, D::Error> {
+        let rows = Vec::<Vec<cell::Kind>>::deserialize(deserializer)?;
+        let height = rows.len();
+        let width = rows.first().map_or(0, Vec::len);
+        if !(MIN_WIDTH..=MAX_WIDTH).contains(&width.saturating_sub(BORDER * 2))
+            || rows.iter().any(|row| row.len() != width)
+        {
+            return Err(de::Error::custom("invalid field width"));
+        }
+        let rows_len = height.saturating_sub(BOTTOM);
+        let visible_height = rows_len.saturating_sub(MIN_BUFFER_HEIGHT).min(rows_len / 2);
+        if visible_height < MIN_HEIGHT || visible_height + buffer_height(visible_height) != rows_len
+        {
+            return Err(de::Error::invalid_length(height, &"field height"));
+        }
+        Ok(Field {
+            width,
+            height,
+            cells: rows.concat(),
+            rows: rows.iter().map(|row| row_bits(row)).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTHS: [usize; 3] = [MIN_WIDTH, DEFAULT_WIDTH, MAX_WIDTH];
+
+    // ビット列がマスの種類と食い違っていないか
+    fn assert_rows(field: &Field) {
+        for y in 0..field.height() {
+            assert_eq!(field.row(y), row_bits(&field[y]), "row {}", y);
+        }
+    }
+
+    fn garbage_line(field: &Field, hole: usize) -> Vec<cell::Kind> {
+        let mut line = field.empty_line();
+        for x in field.columns() {
+            if x != hole {
+                line[x] = cell::GARBAGE;
+            }
+        }
+        line
+    }
+
+    #[test]
+    fn rows_follow_set() {
+        for width in WIDTHS {
+            let mut field = Field::new(width, DEFAULT_HEIGHT);
+            assert_rows(&field);
+            let y = field.floor() - 1;
+            for x in field.columns() {
+                field.set(x, y, cell::GARBAGE);
+                assert_rows(&field);
+            }
+            assert!(field.is_full_line(y));
+            field.set(field.columns().end - 1, y, cell::NONE);
+            assert_rows(&field);
+            assert!(!field.is_full_line(y));
+            assert!(!field.is_empty_line(y));
+        }
+    }
+
+    #[test]
+    fn rows_follow_remove_line() {
+        for width in WIDTHS {
+            let mut field = Field::new(width, DEFAULT_HEIGHT);
+            let bottom = field.floor() - 1;
+            let first = field.columns().start;
+            field.set(first, bottom - 1, cell::GARBAGE);
+            for x in field.columns() {
+                field.set(x, bottom, cell::GARBAGE);
+            }
+            field.remove_line(bottom);
+            assert_rows(&field);
+            assert!(field.is_empty_line(0));
+            assert!(field.is_empty_line(bottom - 1));
+            assert_eq!(field.get(first, bottom), Some(cell::GARBAGE));
+            assert_eq!(field.row(bottom) & field.columns_mask(), 1 << first);
+        }
+    }
+
+    #[test]
+    fn rows_follow_push_line() {
+        for width in WIDTHS {
+            let mut field = Field::new(width, DEFAULT_HEIGHT);
+            let bottom = field.floor() - 1;
+            let hole = field.columns().end - 1;
+            assert!(field.push_line(&garbage_line(&field, hole)));
+            assert!(field.push_line(&garbage_line(&field, field.columns().start)));
+            assert_rows(&field);
+            assert_eq!(field.get(hole, bottom - 1), Some(cell::NONE));
+            assert!(!field.is_full_line(bottom));
+            assert!(!field.is_empty_line(bottom));
+
+            // 一番上の段にブロックがあればあふれる
+            let top = field.columns().start;
+            field.set(top, 0, cell::GARBAGE);
+            assert!(!field.push_line(&garbage_line(&field, hole)));
+            assert_rows(&field);
+        }
+    }
+
+    #[test]
+    fn full_and_empty_lines() {
+        for width in [MIN_WIDTH, MAX_WIDTH] {
+            let mut field = Field::new(width, DEFAULT_HEIGHT);
+            assert_eq!(field.playfield_width(), width);
+            assert_eq!(
+                field.columns_mask().count_ones() as usize,
+                field.playfield_width()
+            );
+            for y in field.rows() {
+                assert!(field.is_empty_line(y));
+                assert!(!field.is_full_line(y));
+            }
+            // 壁のビットは置ける列に含まない
+            assert_eq!(field.row(0) & field.columns_mask(), 0);
+            assert_ne!(field.row(0), 0);
+
+            let y = field.floor() - 1;
+            for x in field.columns() {
+                assert!(!field.is_full_line(y));
+                field.set(x, y, cell::GARBAGE);
+                assert!(!field.is_empty_line(y));
+            }
+            assert!(field.is_full_line(y));
+        }
+    }
+
+    #[test]
+    fn rows_follow_deserialize() {
+        for width in WIDTHS {
+            let mut field = Field::new(width, DEFAULT_HEIGHT);
+            field.push_line(&garbage_line(&field, field.columns().start));
+            let json = serde_json::to_string(&field).unwrap();
+            let loaded: Field = serde_json::from_str(&json).unwrap();
+            assert_rows(&loaded);
+            assert_eq!(loaded, field);
+        }
+    }
+
+    #[test]
+    fn short_field_keeps_buffer() {
+        for height in [MIN_HEIGHT, 8, 19] {
+            let field = Field::new(DEFAULT_WIDTH, height);
+            assert_eq!(field.visible_height(), height);
+            assert_eq!(field.buffer_height(), MIN_BUFFER_HEIGHT);
+            assert_eq!(field.floor(), height + MIN_BUFFER_HEIGHT);
+            let json = serde_json::to_string(&field).unwrap();
+            assert_eq!(serde_json::from_str::<Field>(&json).unwrap(), field);
+        }
+        let field = Field::new(DEFAULT_WIDTH, 30);
+        assert_eq!(field.visible_height(), 30);
+        assert_eq!(field.buffer_height(), 30);
+    }
+
+    #[test]
+    fn reject_invalid_height() {
+        let rows = |height: usize, removed: usize| {
+            let field = Field::new(DEFAULT_WIDTH, height);
+            let rows: Vec<&[cell::Kind]> = field.lines().skip(removed).collect();
+            serde_json::to_string(&rows).unwrap()
+        };
+        // バッファ領域が足りない
+        assert!(serde_json::from_str::<Field>(&rows(8, 12)).is_err());
+        // バッファ領域と可視領域の高さが合わない
+        assert!(serde_json::from_str::<Field>(&rows(30, 1)).is_err());
+        assert!(serde_json::from_str::<Field>(&rows(30, 0)).is_ok());
+    }
+
+    #[test]
+    fn width_is_clamped() {
+        assert_eq!(Field::new(1, DEFAULT_HEIGHT).playfield_width(), MIN_WIDTH);
+        assert_eq!(Field::new(100, DEFAULT_HEIGHT).playfield_width(), MAX_WIDTH);
+    }
+}
@@ -0,0 +1,222 @@
+use super::{cell, field::Field};
+use rand::{seq::index, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// 穴の位置の決め方
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HolePlacement {
+    Clean, // 全ての段で同じ位置
+    #[default]
+    Messy, // 段ごとにばらばら
+}
+
+impl FromStr for HolePlacement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clean" => Ok(HolePlacement::Clean),
+            "messy" => Ok(HolePlacement::Messy),
+            _ => Err(format!("unknown hole placement '{}' (clean, messy)", s)),
+        }
+    }
+}
+
+impl fmt::Display for HolePlacement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            HolePlacement::Clean => "clean",
+            HolePlacement::Messy => "messy",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct GarbageConfig {
+    pub holes: usize, // 1段あたりの穴の数
+    pub placement: HolePlacement,
+}
+
+impl Default for GarbageConfig {
+    fn default() -> GarbageConfig {
+        GarbageConfig {
+            holes: 1,
+            placement: HolePlacement::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Garbage {
+    config: GarbageConfig,
+    pub cleared: usize, // 消したせり上がりの段数
+    rng: ChaCha8Rng,    // テトリミノの出現順に影響しないよう別の乱数を使う
+    last_holes: Vec<usize>,
+}
+
+impl Garbage {
+    pub fn new(seed: u64, config: GarbageConfig) -> Garbage {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        rng.set_stream(1);
+        Garbage {
+            config,
+            cleared: 0,
+            rng,
+            last_holes: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> GarbageConfig {
+        self.config
+    }
+
+    // 穴の列(フィールドのx座標)を決める
+    fn next_holes(&mut self, field: &Field) -> Vec<usize> {
+        if self.config.placement == HolePlacement::Clean && !self.last_holes.is_empty() {
+            return self.last_holes.clone();
+        }
+        // messyでも直前の段と全く同じ穴にはしない
+        let holes = loop {
+            let width = field.playfield_width();
+            let count = self.config.holes.clamp(1, width - 1);
+            let mut holes: Vec<usize> = index::sample(&mut self.rng, width, count)
+                .into_iter()
+                .map(|x| x + field.columns().start)
+                .collect();
+            holes.sort_unstable();
+            if holes != self.last_holes {
+                break holes;
+            }
+        };
+        self.last_holes = holes.clone();
+        holes
+    }
+
+    pub fn next_line(&mut self, field: &Field) -> Vec<cell::Kind> {
+        let mut line = field.empty_line();
+        let holes = self.next_holes(field);
+        for x in field.columns() {
+            if !holes.contains(&x) {
+                line[x] = cell::GARBAGE;
+            }
+        }
+        line
+    }
+}
+
+// せり上がりのブロックを含む段
+pub fn is_garbage_line(line: &[cell::Kind]) -> bool {
+    line.contains(&cell::GARBAGE)
+}
+
+pub fn count_lines(field: &Field) -> usize {
+    field.lines().filter(|line| is_garbage_line(line)).count()
+}
+
+// 揃っている段のうちせり上がりを含むものの数(消去前に数える)
+pub fn count_full_lines(field: &Field) -> usize {
+    field
+        .rows()
+        .filter(|&y| is_garbage_line(&field[y]) && field.is_full_line(y))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn holes(line: &[cell::Kind], field: &Field) -> Vec<usize> {
+        field.columns().filter(|&x| line[x] == cell::NONE).collect()
+    }
+
+    fn lines(config: GarbageConfig, field: &Field, n: usize) -> Vec<Vec<cell::Kind>> {
+        let mut garbage = Garbage::new(5, config);
+        (0..n).map(|_| garbage.next_line(field)).collect()
+    }
+
+    #[test]
+    fn clean_holes_line_up() {
+        let field = Field::default();
+        let config = GarbageConfig {
+            holes: 1,
+            placement: HolePlacement::Clean,
+        };
+        let lines = lines(config, &field, 10);
+        let first = holes(&lines[0], &field);
+        assert_eq!(first.len(), 1);
+        for line in &lines {
+            assert_eq!(holes(line, &field), first);
+            // 壁と番兵はそのまま
+            assert_eq!(line[1], cell::WALL);
+            assert_eq!(line[0], cell::NONE);
+        }
+    }
+
+    #[test]
+    fn messy_holes_move() {
+        let field = Field::default();
+        for count in [1, 3] {
+            let config = GarbageConfig {
+                holes: count,
+                placement: HolePlacement::Messy,
+            };
+            let lines = lines(config, &field, 20);
+            for pair in lines.windows(2) {
+                assert_eq!(holes(&pair[0], &field).len(), count);
+                assert_ne!(holes(&pair[0], &field), holes(&pair[1], &field));
+            }
+        }
+    }
+
+    #[test]
+    fn hole_count_leaves_a_block() {
+        let field = Field::new(4, 20);
+        let config = GarbageConfig {
+            holes: 10,
+            placement: HolePlacement::Messy,
+        };
+        for line in lines(config, &field, 5) {
+            assert_eq!(holes(&line, &field).len(), 3);
+            assert!(is_garbage_line(&line));
+        }
+    }
+
+    #[test]
+    fn same_seed_same_lines() {
+        let field = Field::default();
+        let config = GarbageConfig::default();
+        assert_eq!(lines(config, &field, 10), lines(config, &field, 10));
+    }
+
+    #[test]
+    fn count_garbage_lines() {
+        let mut field = Field::default();
+        let mut garbage = Garbage::new(0, GarbageConfig::default());
+        for _ in 0..3 {
+            let line = garbage.next_line(&field);
+            field.push_line(&line);
+        }
+        assert_eq!(count_lines(&field), 3);
+        assert_eq!(count_full_lines(&field), 0);
+
+        // 穴を埋めた段だけ数える
+        let bottom = field.floor() - 1;
+        for x in holes(&field[bottom], &field) {
+            field.set(x, bottom, cell::T);
+        }
+        assert_eq!(count_full_lines(&field), 1);
+
+        // せり上がりを含まない揃った段は数えない
+        let top = bottom - 3;
+        for x in field.columns() {
+            field.set(x, top, cell::I);
+        }
+        assert!(field.is_full_line(top));
+        assert_eq!(count_full_lines(&field), 1);
+        assert_eq!(count_lines(&field), 3);
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+pub const LINES_PER_LEVEL: usize = 10;
+pub const LEVEL_MAX: usize = 20;
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+pub const FRAME: Duration = Duration::from_nanos(1_000_000_000 / FRAMES_PER_SECOND as u64);
+
+pub const GRAVITY_20G: f64 = 20.0;
+
+// ガイドラインの落下速度(1段落ちるのにかかる秒数)
+pub fn seconds_per_row(level: usize) -> f64 {
+    let level = level.clamp(1, LEVEL_MAX) as f64;
+    (0.8 - (level - 1.0) * 0.007).powf(level - 1.0)
+}
+
+// 1フレームあたりの落下段数(G)
+pub fn gravity(level: usize) -> f64 {
+    let g = 1.0 / (seconds_per_row(level) * FRAMES_PER_SECOND as f64);
+    g.min(GRAVITY_20G)
+}
+
+// 自動落下の間隔と1回あたりの落下段数
+pub fn auto_drop(level: usize) -> (Duration, usize) {
+    drop_interval(gravity(level))
+}
+
+// ソフトドロップ中の落下間隔(factorが無限大なら即座に着地)
+pub fn soft_drop(level: usize, factor: f64) -> (Duration, usize) {
+    drop_interval(gravity(level) * factor)
+}
+
+// 20G以上のときは落下段数がusize::MAX(即座に着地)
+fn drop_interval(g: f64) -> (Duration, usize) {
+    if g < 1.0 {
+        (FRAME.div_f64(g), 1)
+    } else if g >= GRAVITY_20G {
+        (FRAME, usize::MAX)
+    } else {
+        let rows = g.ceil();
+        (FRAME.mul_f64(rows / g), rows as usize)
+    }
+}
+
+// 開始レベルからLINES_PER_LEVELライン消すごとに1つ上がる
+pub fn level_for_lines(start_level: usize, lines: usize) -> usize {
+    start_level + lines / LINES_PER_LEVEL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_up_every_ten_lines() {
+        assert_eq!(level_for_lines(1, 0), 1);
+        assert_eq!(level_for_lines(1, 9), 1);
+        assert_eq!(level_for_lines(1, 10), 2);
+        assert_eq!(level_for_lines(1, 25), 3);
+        // 途中のレベルから始めてもLINES_PER_LEVELごとに上がる
+        assert_eq!(level_for_lines(5, 9), 5);
+        assert_eq!(level_for_lines(5, 10), 6);
+        assert_eq!(level_for_lines(15, 100), 25);
+    }
+
+    #[test]
+    fn guideline_speed() {
+        // ガイドラインの表(秒/段)
+        let table = [
+            1.0, 0.793, 0.6178, 0.47273, 0.3552, 0.262, 0.18968, 0.13473, 0.09388, 0.06415,
+            0.04298, 0.02822, 0.01815, 0.01144, 0.00706,
+        ];
+        for (i, &seconds) in table.iter().enumerate() {
+            let level = i + 1;
+            let error = (seconds_per_row(level) - seconds).abs() / seconds;
+            assert!(error < 1e-3, "level {}: {}", level, seconds_per_row(level));
+        }
+        assert_eq!(seconds_per_row(0), seconds_per_row(1));
+        assert_eq!(seconds_per_row(LEVEL_MAX + 5), seconds_per_row(LEVEL_MAX));
+    }
+
+    #[test]
+    fn drop_interval_per_level() {
+        let (interval, rows) = auto_drop(1);
+        assert_eq!(rows, 1);
+        assert!(interval.abs_diff(Duration::from_secs(1)) < Duration::from_micros(1));
+
+        // 1G以上は毎フレーム複数段落とす
+        let (interval, rows) = auto_drop(17);
+        assert!(gravity(17) > 1.0);
+        assert_eq!(rows, gravity(17).ceil() as usize);
+        assert!(interval >= FRAME);
+
+        assert_eq!(auto_drop(LEVEL_MAX), (FRAME, usize::MAX));
+        assert_eq!(soft_drop(1, f64::INFINITY), (FRAME, usize::MAX));
+        assert_eq!(soft_drop(1, 20.0).1, 1);
+    }
+}
@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub const LOCK_DELAY: Duration = Duration::from_millis(500);
+pub const LOCK_DELAY_RESET_MAX: usize = 15;
+
+// 接地してから固定されるまでの猶予(移動・回転でリセット)
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct LockDelay {
+    elapsed: Duration,
+    reset_count: usize,
+    lowest_y: usize,
+}
+
+impl LockDelay {
+    pub fn new(y: usize) -> LockDelay {
+        LockDelay {
+            elapsed: Duration::ZERO,
+            reset_count: 0,
+            lowest_y: y,
+        }
+    }
+
+    pub fn remaining(&self) -> Duration {
+        if self.reset_count >= LOCK_DELAY_RESET_MAX {
+            return Duration::ZERO;
+        }
+        LOCK_DELAY.saturating_sub(self.elapsed)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    pub fn advance(&mut self, elapsed: Duration) {
+        self.elapsed += elapsed;
+    }
+
+    // 移動・回転に成功したときに呼ぶ
+    // 最下段を更新したらリセット回数も含めて初期化する
+    pub fn on_moved(&mut self, y: usize, grounded: bool) {
+        if y > self.lowest_y {
+            *self = LockDelay::new(y);
+        } else if grounded && self.reset_count < LOCK_DELAY_RESET_MAX {
+            self.elapsed = Duration::ZERO;
+            self.reset_count += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn expires_after_delay() {
+        let mut lock_delay = LockDelay::new(10);
+        lock_delay.advance(LOCK_DELAY - FRAME);
+        assert!(!lock_delay.is_expired());
+        lock_delay.advance(FRAME);
+        assert!(lock_delay.is_expired());
+    }
+
+    #[test]
+    fn move_on_ground_resets_timer() {
+        let mut lock_delay = LockDelay::new(10);
+        lock_delay.advance(LOCK_DELAY - FRAME);
+        lock_delay.on_moved(10, true);
+        assert_eq!(lock_delay.remaining(), LOCK_DELAY);
+    }
+
+    #[test]
+    fn move_in_air_keeps_timer() {
+        let mut lock_delay = LockDelay::new(10);
+        lock_delay.advance(FRAME);
+        lock_delay.on_moved(10, false);
+        assert_eq!(lock_delay.remaining(), LOCK_DELAY - FRAME);
+    }
+
+    #[test]
+    fn resets_are_capped() {
+        let mut lock_delay = LockDelay::new(10);
+        for _ in 0..LOCK_DELAY_RESET_MAX - 1 {
+            lock_delay.advance(FRAME);
+            lock_delay.on_moved(10, true);
+            assert!(!lock_delay.is_expired());
+        }
+        // 上限に達したら時間が残っていてもすぐに固定する
+        lock_delay.on_moved(10, true);
+        assert!(lock_delay.is_expired());
+    }
+
+    #[test]
+    fn lower_row_restores_resets() {
+        let mut lock_delay = LockDelay::new(10);
+        for _ in 0..LOCK_DELAY_RESET_MAX {
+            lock_delay.on_moved(10, true);
+        }
+        assert!(lock_delay.is_expired());
+        lock_delay.on_moved(11, true);
+        assert_eq!(lock_delay.remaining(), LOCK_DELAY);
+        lock_delay.on_moved(11, true);
+        assert!(!lock_delay.is_expired());
+    }
+}
@@ -0,0 +1,257 @@
+use crate::game::tetromino::{Kind, KINDS};
+use rand::{seq::SliceRandom, Rng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+pub trait Randomizer {
+    fn next(&mut self, rng: &mut ChaCha8Rng) -> Kind;
+}
+
+// 全種類をcopies個ずつ袋に入れて、空になるまで取り出す
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bag {
+    copies: usize,
+    pool: Vec<Kind>,
+}
+
+impl Bag {
+    pub fn new(copies: usize) -> Bag {
+        Bag {
+            copies,
+            pool: Vec::new(),
+        }
+    }
+}
+
+impl Randomizer for Bag {
+    fn next(&mut self, rng: &mut ChaCha8Rng) -> Kind {
+        if self.pool.is_empty() {
+            for _ in 0..self.copies {
+                self.pool.extend_from_slice(&KINDS);
+            }
+            self.pool.shuffle(rng);
+        }
+        self.pool.pop().unwrap()
+    }
+}
+
+// TGM方式: 直近4個の履歴にあるものが出たら一定回数まで引き直す
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Tgm {
+    history: VecDeque<Kind>,
+    first: bool,
+}
+
+const TGM_ROLLS: usize = 6;
+
+impl Tgm {
+    pub fn new() -> Tgm {
+        Tgm {
+            history: [Kind::Z, Kind::S, Kind::S, Kind::Z].into(),
+            first: true,
+        }
+    }
+}
+
+impl Default for Tgm {
+    fn default() -> Tgm {
+        Tgm::new()
+    }
+}
+
+impl Randomizer for Tgm {
+    fn next(&mut self, rng: &mut ChaCha8Rng) -> Kind {
+        let kind = if self.first {
+            // 最初はS, Z, Oが出ない
+            self.first = false;
+            *[Kind::I, Kind::J, Kind::L, Kind::T].choose(rng).unwrap()
+        } else {
+            let mut kind = rng.gen();
+            for _ in 1..TGM_ROLLS {
+                if !self.history.contains(&kind) {
+                    break;
+                }
+                kind = rng.gen();
+            }
+            kind
+        };
+        self.history.pop_front();
+        self.history.push_back(kind);
+        kind
+    }
+}
+
+// ファミコン版方式: 直前と同じもの(またはハズレ)が出たら1度だけ引き直す
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Nes {
+    prev: Option<Kind>,
+}
+
+impl Randomizer for Nes {
+    fn next(&mut self, rng: &mut ChaCha8Rng) -> Kind {
+        let kind = match KINDS.get(rng.gen_range(0..=KINDS.len())) {
+            Some(&kind) if Some(kind) != self.prev => kind,
+            _ => rng.gen(),
+        };
+        self.prev = Some(kind);
+        kind
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PureRandom;
+
+impl Randomizer for PureRandom {
+    fn next(&mut self, rng: &mut ChaCha8Rng) -> Kind {
+        rng.gen()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum AnyRandomizer {
+    Bag(Bag),
+    Tgm(Tgm),
+    Nes(Nes),
+    PureRandom(PureRandom),
+}
+
+impl Randomizer for AnyRandomizer {
+    fn next(&mut self, rng: &mut ChaCha8Rng) -> Kind {
+        match self {
+            AnyRandomizer::Bag(r) => r.next(rng),
+            AnyRandomizer::Tgm(r) => r.next(rng),
+            AnyRandomizer::Nes(r) => r.next(rng),
+            AnyRandomizer::PureRandom(r) => r.next(rng),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RandomizerKind {
+    #[default]
+    Bag7,
+    Bag14,
+    Tgm,
+    Nes,
+    PureRandom,
+}
+
+impl RandomizerKind {
+    pub fn create(self) -> AnyRandomizer {
+        match self {
+            RandomizerKind::Bag7 => AnyRandomizer::Bag(Bag::new(1)),
+            RandomizerKind::Bag14 => AnyRandomizer::Bag(Bag::new(2)),
+            RandomizerKind::Tgm => AnyRandomizer::Tgm(Tgm::new()),
+            RandomizerKind::Nes => AnyRandomizer::Nes(Nes::default()),
+            RandomizerKind::PureRandom => AnyRandomizer::PureRandom(PureRandom),
+        }
+    }
+}
+
+impl FromStr for RandomizerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "7bag" => Ok(RandomizerKind::Bag7),
+            "14bag" => Ok(RandomizerKind::Bag14),
+            "tgm" => Ok(RandomizerKind::Tgm),
+            "nes" => Ok(RandomizerKind::Nes),
+            "random" => Ok(RandomizerKind::PureRandom),
+            _ => Err(format!(
+                "unknown randomizer '{}' (7bag, 14bag, tgm, nes, random)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RandomizerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RandomizerKind::Bag7 => "7bag",
+            RandomizerKind::Bag14 => "14bag",
+            RandomizerKind::Tgm => "tgm",
+            RandomizerKind::Nes => "nes",
+            RandomizerKind::PureRandom => "random",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn take(randomizer: &mut AnyRandomizer, rng: &mut ChaCha8Rng, n: usize) -> Vec<Kind> {
+        (0..n).map(|_| randomizer.next(rng)).collect()
+    }
+
+    // 1周ごとに全種類がcopies個ずつ出る
+    fn assert_bag(kind: RandomizerKind, copies: usize) {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut randomizer = kind.create();
+        let size = KINDS.len() * copies;
+        for _ in 0..50 {
+            let cycle = take(&mut randomizer, &mut rng, size);
+            for kind in KINDS {
+                let count = cycle.iter().filter(|&&k| k == kind).count();
+                assert_eq!(count, copies, "{:?} in {:?}", kind, cycle);
+            }
+        }
+    }
+
+    #[test]
+    fn bag7_deals_each_kind_once_per_cycle() {
+        assert_bag(RandomizerKind::Bag7, 1);
+    }
+
+    #[test]
+    fn bag14_deals_each_kind_twice_per_cycle() {
+        assert_bag(RandomizerKind::Bag14, 2);
+    }
+
+    #[test]
+    fn same_seed_same_sequence() {
+        for kind in [
+            RandomizerKind::Bag7,
+            RandomizerKind::Bag14,
+            RandomizerKind::Tgm,
+            RandomizerKind::Nes,
+            RandomizerKind::PureRandom,
+        ] {
+            let sequence = || {
+                let mut rng = ChaCha8Rng::seed_from_u64(7);
+                take(&mut kind.create(), &mut rng, 100)
+            };
+            assert_eq!(sequence(), sequence());
+        }
+    }
+
+    #[test]
+    fn bag_continues_after_save() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let mut randomizer = RandomizerKind::Bag7.create();
+        take(&mut randomizer, &mut rng, 3);
+        let json = serde_json::to_string(&(&randomizer, &rng)).unwrap();
+        let (mut loaded, mut loaded_rng): (AnyRandomizer, ChaCha8Rng) =
+            serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            take(&mut randomizer, &mut rng, 20),
+            take(&mut loaded, &mut loaded_rng, 20)
+        );
+    }
+
+    #[test]
+    fn tgm_never_starts_with_s_z_o() {
+        for seed in 0..100 {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            let first = RandomizerKind::Tgm.create().next(&mut rng);
+            assert!(![Kind::S, Kind::Z, Kind::O].contains(&first));
+        }
+    }
+}
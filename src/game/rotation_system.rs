@@ -0,0 +1,340 @@
+use crate::game::cell;
+use crate::game::tetromino::{Kind, RotateState, Shape, ShapeMask, KINDS, KIND_MAX};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+pub type Kicks = Vec<(isize, isize)>;
+
+// 組み込みの回転法則(名前, 定義ファイル)
+const BUILTIN_SOURCES: [(&str, &str); 4] = [
+    ("srs", include_str!("rotation_systems/srs.toml")),
+    ("srs+", include_str!("rotation_systems/srs_plus.toml")),
+    ("ars", include_str!("rotation_systems/ars.toml")),
+    ("nrs", include_str!("rotation_systems/nrs.toml")),
+];
+
+static BUILTIN: OnceLock<Vec<(&str, RotationSystem)>> = OnceLock::new();
+
+// 定義ファイル(TOML/JSON)の内容
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RotationSystemDef {
+    pub name: String,
+    pub pieces: BTreeMap<String, PieceDef>,
+    #[serde(default)]
+    pub kicks: BTreeMap<String, BTreeMap<String, Kicks>>, // グループ名 -> "0R"などの回転 -> オフセット
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PieceDef {
+    pub shapes: Vec<Vec<String>>, // 0, R, 2, Lの順で4x4以内の形状('.'と' '以外が埋まったマス)
+    #[serde(default)]
+    pub kicks: Option<String>, // 省略時は壁蹴りなし
+    #[serde(default)]
+    pub spawn_offset: (isize, isize),
+}
+
+pub struct RotationSystem {
+    def: RotationSystemDef,
+    shapes: [[Shape; 4]; KIND_MAX],
+    masks: [[ShapeMask; 4]; KIND_MAX], // 当たり判定用
+    spawn_offsets: [(isize, isize); KIND_MAX],
+    kicks: [[[Kicks; 4]; 4]; KIND_MAX], // [種類][回転前][回転後]
+}
+
+const ROTATE_STATES: [RotateState; 4] = [
+    RotateState::_0,
+    RotateState::_R,
+    RotateState::_2,
+    RotateState::_L,
+];
+
+fn state_name(state: RotateState) -> char {
+    match state {
+        RotateState::_0 => '0',
+        RotateState::_R => 'R',
+        RotateState::_2 => '2',
+        RotateState::_L => 'L',
+    }
+}
+
+// 表は大きいので名前だけ出す
+impl fmt::Debug for RotationSystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RotationSystem")
+            .field("name", &self.def.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RotationSystem {
+    pub fn from_def(def: RotationSystemDef) -> Result<RotationSystem, String> {
+        let mut shapes = [[Shape::default(); 4]; KIND_MAX];
+        let mut masks = [[ShapeMask::default(); 4]; KIND_MAX];
+        let mut spawn_offsets = [(0, 0); KIND_MAX];
+        let mut kicks: [[[Kicks; 4]; 4]; KIND_MAX] = Default::default();
+
+        for kind in KINDS {
+            let piece = def
+                .pieces
+                .get(kind.name())
+                .ok_or_else(|| format!("{}: piece {} is not defined", def.name, kind.name()))?;
+
+            if piece.shapes.len() != 4 {
+                return Err(format!(
+                    "{}: piece {} must have 4 shapes",
+                    def.name,
+                    kind.name()
+                ));
+            }
+            for (i, rows) in piece.shapes.iter().enumerate() {
+                shapes[kind as usize][i] = parse_shape(rows, kind.cell_kind())
+                    .map_err(|e| format!("{}: piece {}: {}", def.name, kind.name(), e))?;
+                masks[kind as usize][i] = shape_mask(&shapes[kind as usize][i]);
+            }
+            spawn_offsets[kind as usize] = piece.spawn_offset;
+
+            let group =
+                match &piece.kicks {
+                    Some(name) => Some(def.kicks.get(name).ok_or_else(|| {
+                        format!("{}: kick table {} is not defined", def.name, name)
+                    })?),
+                    None => None,
+                };
+            for from in ROTATE_STATES {
+                for to in ROTATE_STATES {
+                    let key = format!("{}{}", state_name(from), state_name(to));
+                    kicks[kind as usize][from as usize][to as usize] =
+                        match group.and_then(|group| group.get(&key)) {
+                            Some(offsets) if !offsets.is_empty() => offsets.clone(),
+                            _ => vec![(0, 0)],
+                        };
+                }
+            }
+        }
+
+        Ok(RotationSystem {
+            def,
+            shapes,
+            masks,
+            spawn_offsets,
+            kicks,
+        })
+    }
+
+    // 組み込みの名前(srs, srs+, ars, nrs)か定義ファイルのパスから読み込む
+    pub fn load(name: &str) -> Result<&'static RotationSystem, String> {
+        if let Some(rotation_system) = builtin(&name.to_lowercase()) {
+            return Ok(rotation_system);
+        }
+
+        let path = Path::new(name);
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", name, e))?;
+        let def: RotationSystemDef = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&text).map_err(|e| e.to_string())?,
+            _ => toml::from_str(&text).map_err(|e| e.to_string())?,
+        };
+        let rotation_system = RotationSystem::from_def(def)?;
+        Ok(Box::leak(Box::new(rotation_system)))
+    }
+
+    pub fn shape(&self, kind: Kind, state: RotateState) -> Shape {
+        self.shapes[kind as usize][state as usize]
+    }
+
+    pub fn shape_mask(&self, kind: Kind, state: RotateState) -> ShapeMask {
+        self.masks[kind as usize][state as usize]
+    }
+
+    pub fn spawn_offset(&self, kind: Kind) -> (isize, isize) {
+        self.spawn_offsets[kind as usize]
+    }
+
+    pub fn kicks(&self, kind: Kind, from: RotateState, to: RotateState) -> &[(isize, isize)] {
+        &self.kicks[kind as usize][from as usize][to as usize]
+    }
+}
+
+fn parse_shape(rows: &[String], cell_kind: cell::Kind) -> Result<Shape, String> {
+    let mut shape = Shape::default();
+    if rows.len() > 4 {
+        return Err("shape must be at most 4 rows".to_string());
+    }
+    for (y, row) in rows.iter().enumerate() {
+        if row.chars().count() > 4 {
+            return Err("shape must be at most 4 columns".to_string());
+        }
+        for (x, c) in row.chars().enumerate() {
+            if c != '.' && c != ' ' {
+                shape[y][x] = cell_kind;
+            }
+        }
+    }
+    let count = shape.iter().flatten().filter(|&&c| c != cell::NONE).count();
+    if count != 4 {
+        return Err(format!("shape must have 4 blocks, found {}", count));
+    }
+    Ok(shape)
+}
+
+fn shape_mask(shape: &Shape) -> ShapeMask {
+    let mut mask = ShapeMask::default();
+    for (row, line) in mask.iter_mut().zip(shape) {
+        for (x, &kind) in line.iter().enumerate() {
+            if kind != cell::NONE {
+                *row |= 1 << x;
+            }
+        }
+    }
+    mask
+}
+
+pub fn builtin(name: &str) -> Option<&'static RotationSystem> {
+    let builtin = BUILTIN.get_or_init(|| {
+        BUILTIN_SOURCES
+            .iter()
+            .map(|(name, source)| {
+                let def = toml::from_str(source).expect("invalid builtin rotation system");
+                let rotation_system =
+                    RotationSystem::from_def(def).expect("invalid builtin rotation system");
+                (*name, rotation_system)
+            })
+            .collect()
+    });
+    builtin
+        .iter()
+        .find(|(builtin_name, _)| *builtin_name == name)
+        .map(|(_, rotation_system)| rotation_system)
+}
+
+// セーブデータには定義ごと保存して、読み込み時に作り直す
+pub mod as_def {
+    use super::{Deserialize, Deserializer, Serialize, Serializer};
+    use super::{RotationSystem, RotationSystemDef};
+    use serde::de;
+
+    pub fn serialize<S: Serializer>(
+        rotation_system: &&'static RotationSystem,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        rotation_system.def.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<&'static RotationSystem, D::Error> {
+        let def = RotationSystemDef::deserialize(deserializer)?;
+        let rotation_system = RotationSystem::from_def(def).map_err(de::Error::custom)?;
+        Ok(Box::leak(Box::new(rotation_system)))
+    }
+}
+
+pub fn srs() -> &'static RotationSystem {
+    builtin("srs").unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRS: &str = BUILTIN_SOURCES[0].1;
+
+    // SRSの定義の一部を書き換えて読み込む
+    fn from_source(from: &str, to: &str) -> Result<RotationSystem, String> {
+        assert!(SRS.contains(from), "{}", from);
+        let source = SRS.replacen(from, to, 1);
+        let def = toml::from_str(&source).map_err(|e| e.to_string())?;
+        RotationSystem::from_def(def)
+    }
+
+    fn assert_error(from: &str, to: &str, message: &str) {
+        match from_source(from, to) {
+            Ok(_) => panic!("{} -> {} was accepted", from, to),
+            Err(e) => assert!(e.contains(message), "{}", e),
+        }
+    }
+
+    #[test]
+    fn builtins() {
+        for (name, _) in BUILTIN_SOURCES {
+            assert!(builtin(name).is_some(), "{}", name);
+            assert!(RotationSystem::load(&name.to_uppercase()).is_ok());
+        }
+        assert!(builtin("unknown").is_none());
+    }
+
+    #[test]
+    fn srs_tables() {
+        let srs = srs();
+        assert_eq!(
+            srs.kicks(Kind::T, RotateState::_0, RotateState::_R),
+            [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)]
+        );
+        assert_eq!(
+            srs.kicks(Kind::I, RotateState::_0, RotateState::_R),
+            [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)]
+        );
+        // キックの無い回転はその場で回るだけ
+        assert_eq!(
+            srs.kicks(Kind::O, RotateState::_0, RotateState::_R),
+            [(0, 0)]
+        );
+        assert_eq!(
+            srs.shape_mask(Kind::T, RotateState::_0),
+            [0b010, 0b111, 0, 0]
+        );
+        assert_eq!(srs.shape_mask(Kind::I, RotateState::_R), [0b100; 4]);
+    }
+
+    #[test]
+    fn load_file() {
+        let dir = std::env::temp_dir();
+        let toml_path = dir.join(format!("tetris_rs_{}.toml", std::process::id()));
+        fs::write(&toml_path, SRS.replacen("\"SRS\"", "\"custom\"", 1)).unwrap();
+        let loaded = RotationSystem::load(toml_path.to_str().unwrap());
+        fs::remove_file(&toml_path).unwrap();
+        assert_eq!(loaded.unwrap().def.name, "custom");
+
+        let def: RotationSystemDef = toml::from_str(SRS).unwrap();
+        let json_path = dir.join(format!("tetris_rs_{}.json", std::process::id()));
+        fs::write(&json_path, serde_json::to_string(&def).unwrap()).unwrap();
+        let loaded = RotationSystem::load(json_path.to_str().unwrap());
+        fs::remove_file(&json_path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(
+            loaded.kicks(Kind::J, RotateState::_R, RotateState::_2),
+            srs().kicks(Kind::J, RotateState::_R, RotateState::_2)
+        );
+    }
+
+    #[test]
+    fn load_errors() {
+        assert!(RotationSystem::load("/nonexistent/rotation.toml").is_err());
+        assert!(from_source("name = \"SRS\"", "name = ").is_err());
+        assert_error("[pieces.T]", "[pieces.X]", "piece T is not defined");
+        assert_error(
+            "    [\".T..\", \"TT..\", \".T..\", \"....\"],\n",
+            "",
+            "piece T must have 4 shapes",
+        );
+        assert_error("[\".T..\", \"TTT.\"", "[\".TT.\", \"TTT.\"", "found 5");
+        assert_error(
+            "[\".T..\", \"TTT.\"",
+            "[\".T...\", \"TTT.\"",
+            "at most 4 columns",
+        );
+        assert_error(
+            "[\".T..\", \"TTT.\", \"....\", \"....\"]",
+            "[\".T..\", \"TTT.\", \"....\", \"....\", \"....\"]",
+            "at most 4 rows",
+        );
+        assert_error(
+            "kicks = \"JLSTZ\"\nshapes = [\n    [\".T..\"",
+            "kicks = \"T\"\nshapes = [\n    [\".T..\"",
+            "kick table T is not defined",
+        );
+    }
+}
@@ -0,0 +1,89 @@
+// ガイドライン準拠の得点計算
+
+pub const COMBO_POINTS: usize = 50;
+pub const SOFT_DROP_POINTS: usize = 1;
+pub const HARD_DROP_POINTS: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TSpin {
+    Mini,
+    Full,
+}
+
+// レベル倍率をかける前の基本点
+pub fn action_points(lines: usize, t_spin: Option<TSpin>) -> usize {
+    match (t_spin, lines) {
+        (None, 0) => 0,
+        (None, 1) => 100,
+        (None, 2) => 300,
+        (None, 3) => 500,
+        (None, _) => 800,
+        (Some(TSpin::Mini), 0) => 100,
+        (Some(TSpin::Mini), 1) => 200,
+        (Some(TSpin::Mini), _) => 400,
+        (Some(TSpin::Full), 0) => 400,
+        (Some(TSpin::Full), 1) => 800,
+        (Some(TSpin::Full), 2) => 1200,
+        (Some(TSpin::Full), _) => 1600,
+    }
+}
+
+// Back-to-Backの対象になる消去(テトリス、ライン消去を伴うTスピン)
+pub fn is_difficult(lines: usize, t_spin: Option<TSpin>) -> bool {
+    lines >= 4 || (lines > 0 && t_spin.is_some())
+}
+
+pub fn perfect_clear_points(lines: usize, back_to_back: bool) -> usize {
+    match lines {
+        0 => 0,
+        1 => 800,
+        2 => 1200,
+        3 => 1800,
+        _ if back_to_back => 3200,
+        _ => 2000,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_clear_points() {
+        assert_eq!(action_points(0, None), 0);
+        assert_eq!(action_points(1, None), 100);
+        assert_eq!(action_points(2, None), 300);
+        assert_eq!(action_points(3, None), 500);
+        assert_eq!(action_points(4, None), 800);
+    }
+
+    #[test]
+    fn t_spin_points() {
+        assert_eq!(action_points(0, Some(TSpin::Mini)), 100);
+        assert_eq!(action_points(1, Some(TSpin::Mini)), 200);
+        assert_eq!(action_points(2, Some(TSpin::Mini)), 400);
+        assert_eq!(action_points(0, Some(TSpin::Full)), 400);
+        assert_eq!(action_points(1, Some(TSpin::Full)), 800);
+        assert_eq!(action_points(2, Some(TSpin::Full)), 1200);
+        assert_eq!(action_points(3, Some(TSpin::Full)), 1600);
+    }
+
+    #[test]
+    fn difficult_clears() {
+        assert!(is_difficult(4, None));
+        assert!(is_difficult(1, Some(TSpin::Mini)));
+        assert!(is_difficult(2, Some(TSpin::Full)));
+        assert!(!is_difficult(3, None));
+        // ラインを消さないTスピンはBack-to-Backの対象にならない
+        assert!(!is_difficult(0, Some(TSpin::Full)));
+    }
+
+    #[test]
+    fn perfect_clear_bonus() {
+        assert_eq!(perfect_clear_points(1, false), 800);
+        assert_eq!(perfect_clear_points(2, false), 1200);
+        assert_eq!(perfect_clear_points(3, false), 1800);
+        assert_eq!(perfect_clear_points(4, false), 2000);
+        assert_eq!(perfect_clear_points(4, true), 3200);
+    }
+}
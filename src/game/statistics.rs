@@ -0,0 +1,62 @@
+use super::event::{ClearKind, GameEvent};
+use super::GameOverReason;
+use serde::{Deserialize, Serialize};
+
+// イベントから集計する
+// 項目を増やす前のセーブデータやリプレイも読めるよう、無い項目は0にする
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Statistics {
+    pub pieces: usize,
+    pub holds: usize,
+    pub tetrises: usize,
+    pub t_spins: usize, // ラインを消したもののみ
+    pub perfect_clears: usize,
+    pub max_combo: usize,
+    pub garbage_received: usize,
+    pub game_over_reason: Option<GameOverReason>,
+}
+
+impl Statistics {
+    pub fn record(&mut self, event: &GameEvent) {
+        match event {
+            GameEvent::PieceLocked { .. } => self.pieces += 1,
+            GameEvent::Hold { .. } => self.holds += 1,
+            GameEvent::LinesCleared {
+                kind,
+                perfect_clear,
+                ..
+            } => {
+                match kind {
+                    ClearKind::Tetris => self.tetrises += 1,
+                    ClearKind::TSpinMini(_) | ClearKind::TSpin(_) => self.t_spins += 1,
+                    _ => (),
+                }
+                if *perfect_clear {
+                    self.perfect_clears += 1;
+                }
+            }
+            GameEvent::ComboChanged { combo: Some(combo) } => {
+                self.max_combo = self.max_combo.max(*combo);
+            }
+            GameEvent::GarbageAdded { rows } => self.garbage_received += rows,
+            GameEvent::GameOver(reason) => self.game_over_reason = Some(*reason),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_without_new_fields() {
+        let statistics: Statistics =
+            serde_json::from_str(r#"{"pieces":12,"game_over_reason":null}"#).unwrap();
+        assert_eq!(statistics.pieces, 12);
+        assert_eq!(statistics.holds, 0);
+        assert_eq!(statistics.garbage_received, 0);
+        assert!(statistics.game_over_reason.is_none());
+    }
+}
@@ -1,15 +1,27 @@
 use rand::{
     distributions::{Distribution, Standard},
-    seq::SliceRandom,
-    thread_rng, Rng,
+    Rng,
 };
 
-use crate::game::cell::{self, I, J, L, O, S, T, Z};
+use serde::{Deserialize, Serialize};
+
+use crate::game::rotation_system::{self, RotationSystem};
+use crate::game::{cell, field};
 
 // テトリミノの種類
-const KIND_MAX: usize = 7;
+pub const KIND_MAX: usize = 7;
+
+pub const KINDS: [Kind; KIND_MAX] = [
+    Kind::I,
+    Kind::O,
+    Kind::S,
+    Kind::Z,
+    Kind::J,
+    Kind::L,
+    Kind::T,
+];
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Kind {
     #[default]
     I,
@@ -21,7 +33,33 @@ pub enum Kind {
     T,
 }
 
-#[derive(Clone, Copy, Default)]
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::I => "I",
+            Kind::O => "O",
+            Kind::S => "S",
+            Kind::Z => "Z",
+            Kind::J => "J",
+            Kind::L => "L",
+            Kind::T => "T",
+        }
+    }
+
+    pub fn cell_kind(self) -> cell::Kind {
+        match self {
+            Kind::I => cell::I,
+            Kind::O => cell::O,
+            Kind::S => cell::S,
+            Kind::Z => cell::Z,
+            Kind::J => cell::J,
+            Kind::L => cell::L,
+            Kind::T => cell::T,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum RotateState {
     #[default]
     _0, // spawn state
@@ -30,35 +68,76 @@ pub enum RotateState {
     _L, // state resulting from a counter-clockwise ("left") rotation from spawn
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Rotation {
+    Left,
+    Right,
+    Half, // 180度回転
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Tetromino {
     kind: Kind,
     rotate_state: RotateState,
+    #[serde(skip, default = "rotation_system::srs")] // 読み込み後にGameのものを設定し直す
+    rotation_system: &'static RotationSystem,
+}
+
+impl Default for Tetromino {
+    fn default() -> Self {
+        Tetromino::new(Kind::default(), rotation_system::srs())
+    }
 }
 
 pub type Shape = [[usize; 4]; 4];
-pub type WallKickOffsets = [(isize, isize); 5];
+pub type ShapeMask = [field::Row; 4]; // 各段のブロックをビットで表したもの(x列目が下からxビット目)
+pub type WallKickOffsets = &'static [(isize, isize)];
 
 impl Tetromino {
-    pub fn get_shape(&self) -> Shape {
-        let mut shape = SHAPES[self.kind as usize];
-        let (rotate_count, rotate_size) = match self.kind {
-            Kind::I => (self.rotate_state as usize, 4),
-            Kind::O => (0, 0),
-            _ => (self.rotate_state as usize, 3),
-        };
+    pub fn new(kind: Kind, rotation_system: &'static RotationSystem) -> Tetromino {
+        Tetromino::with_rotate_state(kind, RotateState::_0, rotation_system)
+    }
 
-        for _ in 0..rotate_count {
-            let mut new_shape: Shape = Default::default();
-            for y in 0..rotate_size {
-                for x in 0..rotate_size {
-                    new_shape[y][x] = shape[rotate_size - 1 - x][y];
-                }
-            }
-            shape = new_shape;
+    pub fn with_rotation_system(self, rotation_system: &'static RotationSystem) -> Tetromino {
+        Tetromino {
+            rotation_system,
+            ..self
+        }
+    }
+
+    // 向きを指定して作る
+    pub fn with_rotate_state(
+        kind: Kind,
+        rotate_state: RotateState,
+        rotation_system: &'static RotationSystem,
+    ) -> Tetromino {
+        Tetromino {
+            kind,
+            rotate_state,
+            rotation_system,
         }
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
 
-        shape
+    pub fn rotate_state(&self) -> RotateState {
+        self.rotate_state
+    }
+
+    pub fn get_shape(&self) -> Shape {
+        self.rotation_system.shape(self.kind, self.rotate_state)
+    }
+
+    pub fn shape_mask(&self) -> ShapeMask {
+        self.rotation_system
+            .shape_mask(self.kind, self.rotate_state)
+    }
+
+    // 出現位置の補正
+    pub fn spawn_offset(&self) -> (isize, isize) {
+        self.rotation_system.spawn_offset(self.kind)
     }
 
     pub fn rotate_right(&self) -> Tetromino {
@@ -69,8 +148,8 @@ impl Tetromino {
             RotateState::_L => RotateState::_0,
         };
         Tetromino {
-            kind: self.kind,
             rotate_state,
+            ..*self
         }
     }
 
@@ -82,57 +161,39 @@ impl Tetromino {
             RotateState::_R => RotateState::_0,
         };
         Tetromino {
-            kind: self.kind,
             rotate_state,
+            ..*self
         }
     }
 
-    pub fn rotate_right_wall_kick_offsets(&self) -> WallKickOffsets {
-        match self.kind {
-            Kind::O => [(0, 0), (0, 0), (0, 0), (0, 0), (0, 0)],
-            Kind::I => match self.rotate_state {
-                RotateState::_0 => [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)], // 0->R
-                RotateState::_R => [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)], // R->2
-                RotateState::_2 => [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)], // 2->L
-                RotateState::_L => [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)], // L->0
-            },
-            _ => match self.rotate_state {
-                RotateState::_0 => [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)], // 0->R
-                RotateState::_R => [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],   // R->2
-                RotateState::_2 => [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],    // 2->L
-                RotateState::_L => [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)], // L->0
-            },
+    pub fn rotate_180(&self) -> Tetromino {
+        let rotate_state = match self.rotate_state {
+            RotateState::_0 => RotateState::_2,
+            RotateState::_R => RotateState::_L,
+            RotateState::_2 => RotateState::_0,
+            RotateState::_L => RotateState::_R,
+        };
+        Tetromino {
+            rotate_state,
+            ..*self
         }
     }
 
-    pub fn rotate_left_wall_kick_offsets(&self) -> WallKickOffsets {
-        match self.kind {
-            Kind::O => [(0, 0), (0, 0), (0, 0), (0, 0), (0, 0)],
-            Kind::I => match self.rotate_state {
-                RotateState::_R => [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)], // R->0
-                RotateState::_2 => [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)], // 2->R
-                RotateState::_L => [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)], // L->2
-                RotateState::_0 => [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)], // 0->L
-            },
-            _ => match self.rotate_state {
-                RotateState::_R => [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)], // R->0
-                RotateState::_2 => [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)], // 2->R
-                RotateState::_L => [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)], // L->2
-                RotateState::_0 => [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],  // 0->L
-            },
+    pub fn rotate(&self, rotation: Rotation) -> Tetromino {
+        match rotation {
+            Rotation::Left => self.rotate_left(),
+            Rotation::Right => self.rotate_right(),
+            Rotation::Half => self.rotate_180(),
         }
     }
 
+    pub fn wall_kick_offsets(&self, rotation: Rotation) -> WallKickOffsets {
+        let to = self.rotate(rotation).rotate_state;
+        self.rotation_system.kicks(self.kind, self.rotate_state, to)
+    }
+
     pub fn cell_kind(&self) -> cell::Kind {
-        match self.kind {
-            Kind::I => cell::I,
-            Kind::O => cell::O,
-            Kind::S => cell::S,
-            Kind::Z => cell::Z,
-            Kind::J => cell::J,
-            Kind::L => cell::L,
-            Kind::T => cell::T,
-        }
+        self.kind.cell_kind()
     }
 }
 
@@ -149,74 +210,3 @@ impl Distribution<Kind> for Standard {
         }
     }
 }
-
-// テトリミノの形状
-const SHAPES: [Shape; KIND_MAX] = [
-    [
-        // I
-        [0, 0, 0, 0],
-        [I, I, I, I],
-        [0, 0, 0, 0],
-        [0, 0, 0, 0],
-    ],
-    [
-        // O
-        [0, O, O, 0],
-        [0, O, O, 0],
-        [0, 0, 0, 0],
-        [0, 0, 0, 0],
-    ],
-    [
-        // S
-        [0, S, S, 0],
-        [S, S, 0, 0],
-        [0, 0, 0, 0],
-        [0, 0, 0, 0],
-    ],
-    [
-        // Z
-        [Z, Z, 0, 0],
-        [0, Z, Z, 0],
-        [0, 0, 0, 0],
-        [0, 0, 0, 0],
-    ],
-    [
-        // J
-        [J, 0, 0, 0],
-        [J, J, J, 0],
-        [0, 0, 0, 0],
-        [0, 0, 0, 0],
-    ],
-    [
-        // L
-        [0, 0, L, 0],
-        [L, L, L, 0],
-        [0, 0, 0, 0],
-        [0, 0, 0, 0],
-    ],
-    [
-        // T
-        [0, T, 0, 0],
-        [T, T, T, 0],
-        [0, 0, 0, 0],
-        [0, 0, 0, 0],
-    ],
-];
-
-pub fn gen_tetromino_7() -> [Tetromino; KIND_MAX] {
-    let mut rng = thread_rng();
-    let mut kinds = [
-        Kind::I,
-        Kind::O,
-        Kind::S,
-        Kind::Z,
-        Kind::J,
-        Kind::L,
-        Kind::T,
-    ];
-    kinds.shuffle(&mut rng);
-    kinds.map(|kind| Tetromino {
-        kind,
-        rotate_state: RotateState::_0,
-    })
-}
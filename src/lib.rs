@@ -0,0 +1,16 @@
+// ゲームの中核(フィールド、テトリミノ、エンジン)とAIをライブラリとして公開する
+// 端末での操作や記録の保存はバイナリ側(main.rs)で行う
+
+pub mod ai;
+pub mod game;
+
+pub use ai::eval;
+pub use ai::search::{search, SearchConfig};
+pub use game::engine::{Action, Engine, Input, InputConfig};
+pub use game::event::{ClearKind, GameEvent};
+pub use game::field::Field;
+pub use game::tetromino::{Kind, RotateState, Rotation, Tetromino, KINDS};
+pub use game::{
+    erase_line, fix_tetromino, hard_drop_pos, is_collision, is_perfect_clear, is_top_out, Game,
+    GameConfig, GameOverReason, Goal, Position,
+};
@@ -1,31 +1,264 @@
-mod ai;
-mod game;
 mod play;
+mod records;
+mod replay;
+mod save;
+mod simulate;
 mod ui;
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use tetris::ai::search::SearchConfig;
+use tetris::game::{
+    engine::InputConfig,
+    field,
+    garbage::{GarbageConfig, HolePlacement},
+    randomizer::RandomizerKind,
+    rotation_system::RotationSystem,
+    Game, GameConfig, Goal,
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Arg {
     #[command(subcommand)]
     mode: Option<Mode>,
+
+    /// Starting level
+    #[arg(long, default_value_t = 1, global = true)]
+    level: usize,
+
+    /// Seed for the piece sequence (random if omitted)
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+
+    /// Piece randomizer: 7bag, 14bag, tgm, nes or random
+    #[arg(long, default_value_t, global = true)]
+    randomizer: RandomizerKind,
+
+    /// Rotation system: srs, srs+, ars, nrs or a path to a TOML/JSON definition
+    #[arg(long, default_value = "srs", value_parser = RotationSystem::load, global = true)]
+    rotation_system: &'static RotationSystem,
+
+    /// Field width in columns (4 to 60)
+    #[arg(long, default_value_t = field::DEFAULT_WIDTH, value_parser = parse_width, global = true)]
+    width: usize,
+
+    /// Visible field height in rows (at least 4)
+    #[arg(long, default_value_t = field::DEFAULT_HEIGHT, value_parser = parse_height, global = true)]
+    height: usize,
+
+    /// Delayed auto shift in milliseconds
+    #[arg(long, default_value_t = 167, global = true)]
+    das: u64,
+
+    /// Auto repeat rate in milliseconds (0 = instantly to the wall)
+    #[arg(long, default_value_t = 33, global = true)]
+    arr: u64,
+
+    /// Soft drop speed as a multiple of gravity ("inf" = instantly to the floor)
+    #[arg(long, default_value_t = 20.0, value_parser = parse_soft_drop_factor, global = true)]
+    sdf: f64,
+
+    /// Resume the game saved when quitting last time
+    #[arg(long, global = true)]
+    resume: bool,
+
+    /// Where to record the replay (default: ~/.tetris_replay.json)
+    #[arg(long, global = true)]
+    record: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
 enum Mode {
     Normal,
-    Auto,
+    Auto {
+        #[command(flatten)]
+        ai: AiArg,
+    },
+    /// Clear the given number of lines as fast as possible
+    Sprint {
+        /// Number of lines to clear
+        #[arg(long, default_value_t = 40)]
+        lines: usize,
+    },
+    /// Score as many points as possible within the time limit
+    Ultra {
+        /// Time limit in seconds
+        #[arg(long, default_value_t = 120)]
+        time: u64,
+    },
+    /// Dig through the given number of garbage lines as fast as possible
+    Dig {
+        /// Number of garbage lines to clear
+        #[arg(long, default_value_t = 10)]
+        lines: usize,
+
+        /// Number of holes in each garbage line
+        #[arg(long, default_value_t = 1)]
+        holes: usize,
+
+        /// Hole placement: clean (same column) or messy (random for each line)
+        #[arg(long, default_value_t)]
+        placement: HolePlacement,
+    },
+    /// Run AI games without the UI and print aggregate results
+    Simulate {
+        /// Number of games
+        #[arg(long, default_value_t = 100)]
+        games: usize,
+
+        /// Number of worker threads (default: available parallelism)
+        #[arg(long)]
+        threads: Option<usize>,
+
+        /// Stop each game after this many pieces (0 = until top out)
+        #[arg(long, default_value_t = 1000)]
+        pieces: usize,
+
+        /// Stop each game after this many seconds of real time
+        #[arg(long)]
+        time: Option<u64>,
+
+        #[command(flatten)]
+        ai: AiArg,
+    },
+    /// Play back a recorded replay
+    Replay {
+        /// Replay file
+        file: PathBuf,
+    },
+}
+
+// AIの先読みの設定
+#[derive(Args)]
+struct AiArg {
+    /// Number of pieces the AI plans ahead, including the current one
+    #[arg(long, default_value_t = SearchConfig::default().depth)]
+    depth: usize,
+
+    /// Number of candidates the AI keeps at each depth
+    #[arg(long, default_value_t = SearchConfig::default().width)]
+    beam_width: usize,
+
+    /// Thinking time per piece in milliseconds (0 = unlimited, the only reproducible setting)
+    #[arg(long, default_value_t = 0)]
+    think_time: u64,
+}
+
+impl AiArg {
+    fn search_config(&self) -> SearchConfig {
+        SearchConfig {
+            depth: self.depth.max(1),
+            width: self.beam_width.max(1),
+            time_budget: (self.think_time > 0).then(|| Duration::from_millis(self.think_time)),
+        }
+    }
+}
+
+fn parse_width(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(width) if (field::MIN_WIDTH..=field::MAX_WIDTH).contains(&width) => Ok(width),
+        _ => Err(format!(
+            "must be a number from {} to {}",
+            field::MIN_WIDTH,
+            field::MAX_WIDTH
+        )),
+    }
+}
+
+fn parse_height(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(height) if height >= field::MIN_HEIGHT => Ok(height),
+        _ => Err(format!("must be a number at least {}", field::MIN_HEIGHT)),
+    }
+}
+
+fn parse_soft_drop_factor(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(factor) if factor > 0.0 => Ok(factor),
+        _ => Err("must be a positive number or \"inf\"".to_string()),
+    }
 }
 
 fn main() {
     let arg = Arg::parse();
+    if let Some(Mode::Replay { file }) = &arg.mode {
+        if let Err(err) = replay::load(file).and_then(|replay| play::playback(&replay)) {
+            println!("Error: {}", err);
+        }
+        return;
+    }
+
+    let goal = match arg.mode {
+        Some(Mode::Sprint { lines }) => Goal::Lines(lines.max(1)),
+        Some(Mode::Ultra { time }) => Goal::Time(Duration::from_secs(time.max(1))),
+        Some(Mode::Dig { lines, .. }) => Goal::Garbage(lines.max(1)),
+        _ => Goal::Endless,
+    };
+    let garbage = match arg.mode {
+        Some(Mode::Dig {
+            holes, placement, ..
+        }) => GarbageConfig { holes, placement },
+        _ => Default::default(),
+    };
+    let config = GameConfig {
+        level: arg.level,
+        seed: arg.seed.unwrap_or_else(rand::random),
+        randomizer: arg.randomizer,
+        rotation_system: arg.rotation_system,
+        goal,
+        garbage,
+        width: arg.width,
+        height: arg.height,
+    };
+    if let Some(Mode::Simulate {
+        games,
+        threads,
+        pieces,
+        time,
+        ai,
+    }) = &arg.mode
+    {
+        let (games, pieces) = (*games, *pieces);
+        let threads =
+            threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+        let limit = simulate::Limit {
+            pieces: (pieces > 0).then_some(pieces),
+            time: time.map(Duration::from_secs),
+        };
+        simulate::simulate(&config, &ai.search_config(), games, threads, limit);
+        println!("Seed: {}", config.seed);
+        return;
+    }
+    let input = InputConfig {
+        das: Duration::from_millis(arg.das),
+        arr: Duration::from_millis(arg.arr),
+        soft_drop_factor: arg.sdf,
+    };
+    let game = if arg.resume {
+        match save::load() {
+            Ok(game) => game,
+            Err(err) => {
+                println!("Error: {}", err);
+                return;
+            }
+        }
+    } else {
+        Game::new(&config)
+    };
+    let seed = game.seed;
     let result = match arg.mode {
-        None | Some(Mode::Normal) => play::normal(),
-        Some(Mode::Auto) => play::auto(),
+        Some(Mode::Auto { ai }) => play::auto(game, &ai.search_config()),
+        _ => {
+            let record = arg.record.or_else(replay::default_path);
+            play::normal(game, &input, record.as_deref())
+        }
     };
 
     if let Err(err) = result {
         println!("Error: {}", err);
     }
+    println!("Seed: {}", seed);
 }
@@ -1,133 +1,279 @@
-use crate::ai::eval;
-use crate::game::*;
+use crate::records::{Race, Records};
+use crate::replay::{self, Replay};
+use crate::save;
 use crate::ui;
-use crossterm::event::{self, Event, KeyCode, KeyEvent};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
 use std::error::Error;
+use std::path::Path;
 use std::{thread, time};
+use tetris::ai::search::{search, SearchConfig};
+use tetris::game::engine::{Action, Engine, Input, InputConfig};
+use tetris::game::event::GameEvent;
+use tetris::game::*;
 
-pub fn normal() -> Result<(), Box<dyn Error>> {
-    let mut game = Game::new();
-    let mut ui = ui::Ui::new()?;
+enum Outcome {
+    Quit,
+    GameOver,
+    Cleared,
+}
 
-    let mut next_auto_drop = time::Instant::now() + time::Duration::from_millis(1000);
-    loop {
-        ui.draw(&game)?;
+// record: リプレイの保存先(Noneなら記録しない)
+pub fn normal(
+    game: Game,
+    input: &InputConfig,
+    record: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let mut ui = ui::Ui::new(&game.field)?;
+    let mut engine = Engine::new(game, *input);
+    let mut replay = Replay::new(&engine.game, *input);
+    let outcome = run(&mut ui, &mut engine, &mut replay)?;
+    let game = &engine.game;
+    let saved = match outcome {
+        // 中断したら続きから再開できるように保存する
+        Outcome::Quit => save::save(game),
+        Outcome::GameOver => {
+            let _ = ui.game_over(game);
+            Ok(())
+        }
+        Outcome::Cleared => finish(&mut ui, game),
+    };
+    ui.shutdown()?;
+    if let Some(path) = record {
+        replay::save(&replay, path)?;
+    }
+    saved
+}
 
-        let wait_duration = next_auto_drop
-            .checked_duration_since(time::Instant::now())
-            .unwrap_or(time::Duration::ZERO);
+// 目標達成時の結果表示と自己ベストの更新
+fn finish(ui: &mut ui::Ui, game: &Game) -> Result<(), Box<dyn Error>> {
+    match game.goal {
+        Goal::Endless => Ok(()),
+        Goal::Lines(lines) => finish_race(ui, game, Race::Sprint, lines),
+        Goal::Garbage(lines) => finish_race(ui, game, Race::Dig(game.garbage.config()), lines),
+        Goal::Time(_) => {
+            let _ = ui.ultra_result(game);
+            Ok(())
+        }
+    }
+}
 
-        if !event::poll(wait_duration)? {
-            let new_pos = Position {
-                x: game.pos.x,
-                y: game.pos.y + 1,
-            };
-            if !is_collision(&game.field, &new_pos, &game.tetromino) {
-                game.pos = new_pos;
-            } else if landing(&mut game).is_err() {
-                let _ = ui.game_over(&game);
-                return ui.shutdown();
-            }
-            next_auto_drop = time::Instant::now() + time::Duration::from_millis(1000);
-            continue;
+fn finish_race(
+    ui: &mut ui::Ui,
+    game: &Game,
+    race: Race,
+    lines: usize,
+) -> Result<(), Box<dyn Error>> {
+    // 自己ベストは標準の大きさのフィールドでのみ記録する
+    if game.field.playfield_width() != field::DEFAULT_WIDTH
+        || game.field.visible_height() != field::DEFAULT_HEIGHT
+    {
+        let _ = ui.race_result(game, None, false);
+        return Ok(());
+    }
+    let mut records = Records::load();
+    let best = records.best_time(race, lines);
+    let new_record = records.update_time(race, lines, game.elapsed);
+    let _ = ui.race_result(game, best, new_record);
+    if new_record {
+        records.save()?;
+    }
+    Ok(())
+}
+
+fn key_action(code: KeyCode) -> Option<Action> {
+    match code {
+        KeyCode::Left => Some(Action::Left),
+        KeyCode::Right => Some(Action::Right),
+        KeyCode::Down => Some(Action::SoftDrop),
+        KeyCode::Up => Some(Action::HardDrop),
+        KeyCode::Char('s') => Some(Action::SonicDrop),
+        KeyCode::Char('z') => Some(Action::RotateLeft),
+        KeyCode::Char('x') => Some(Action::RotateRight),
+        KeyCode::Char('a') => Some(Action::Rotate180),
+        KeyCode::Char(' ') => Some(Action::Hold),
+        _ => None,
+    }
+}
+
+// 離したことが分からない端末では押すたびに(キーリピートも)押して離したことにする
+fn push_key_inputs(inputs: &mut Vec<Input>, key: KeyEvent, key_release: bool) {
+    let Some(action) = key_action(key.code) else {
+        return;
+    };
+    match key.kind {
+        KeyEventKind::Press if key_release => inputs.push(Input::Press(action)),
+        KeyEventKind::Release if key_release => inputs.push(Input::Release(action)),
+        KeyEventKind::Press | KeyEventKind::Repeat if !key_release => {
+            inputs.push(Input::Press(action));
+            inputs.push(Input::Release(action));
         }
+        _ => (),
+    }
+}
 
-        let result = match event::read()? {
-            Event::Key(key) => match process_key_input(&mut game, key) {
-                Some(result) => result,
-                _ => continue,
-            },
-            _ => continue,
-        };
+fn run(
+    ui: &mut ui::Ui,
+    engine: &mut Engine,
+    replay: &mut Replay,
+) -> Result<Outcome, Box<dyn Error>> {
+    let key_release = ui.key_release_supported();
+    let mut next_frame = time::Instant::now();
+    loop {
+        ui.draw(&engine.game)?;
 
-        match result {
-            KeyInputProcessResult::NextAutoDropInstant(instant) => {
-                next_auto_drop = instant;
-            }
-            KeyInputProcessResult::GameOver => {
-                let _ = ui.game_over(&game);
-                return ui.shutdown();
+        // 次のフレームまでに届いたキー入力をまとめて渡す
+        let mut inputs = Vec::new();
+        next_frame += gravity::FRAME;
+        while event::poll(next_frame.saturating_duration_since(time::Instant::now()))? {
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.code == KeyCode::Char('q') && key.kind == KeyEventKind::Press {
+                return Ok(Outcome::Quit);
             }
-            KeyInputProcessResult::QuitGame => {
-                return ui.shutdown();
+            push_key_inputs(&mut inputs, key, key_release);
+        }
+
+        replay.record(engine.frame(), &inputs);
+        for event in engine.step(&inputs) {
+            match event {
+                GameEvent::GameOver(_) => return Ok(Outcome::GameOver),
+                GameEvent::GoalReached => return Ok(Outcome::Cleared),
+                _ => ui.notify(&event),
             }
         }
     }
 }
 
-enum KeyInputProcessResult {
-    NextAutoDropInstant(time::Instant),
-    QuitGame,
-    GameOver,
+const REPLAY_SPEEDS: [f64; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const REPLAY_SEEK: time::Duration = time::Duration::from_secs(5);
+
+// 再生位置まで記録された入力でエンジンを進める
+struct Playback<'a> {
+    replay: &'a Replay,
+    engine: Engine,
+    next: usize,              // 次に渡す入力
+    position: time::Duration, // 再生位置
 }
 
-fn process_key_input(game: &mut Game, key: KeyEvent) -> Option<KeyInputProcessResult> {
-    match key.code {
-        KeyCode::Down => {
-            let new_pos = Position {
-                x: game.pos.x,
-                y: game.pos.y + 1,
-            };
-            move_tetromino(game, new_pos);
-            return Some(KeyInputProcessResult::NextAutoDropInstant(
-                time::Instant::now() + time::Duration::from_millis(1000),
-            ));
-        }
-        KeyCode::Left => {
-            let new_pos = Position {
-                x: game.pos.x.checked_sub(1).unwrap_or(game.pos.x),
-                y: game.pos.y,
-            };
-            move_tetromino(game, new_pos);
+impl<'a> Playback<'a> {
+    fn new(replay: &Replay) -> Playback<'_> {
+        Playback {
+            replay,
+            engine: Engine::new(replay.game.clone(), replay.input),
+            next: 0,
+            position: time::Duration::ZERO,
         }
-        KeyCode::Right => {
-            let new_pos = Position {
-                x: game.pos.x + 1,
-                y: game.pos.y,
-            };
-            move_tetromino(game, new_pos);
-        }
-        KeyCode::Up => {
-            hard_drop(game);
-            if landing(game).is_err() {
-                return Some(KeyInputProcessResult::GameOver);
-            }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.engine.is_finished() || self.engine.frame() >= self.replay.frames
+    }
+
+    fn advance(&mut self, elapsed: time::Duration) -> Vec<GameEvent> {
+        let mut events = Vec::new();
+        self.position += elapsed;
+        let target = (self.position.as_nanos() / gravity::FRAME.as_nanos()) as u64;
+        while self.engine.frame() < target && !self.is_finished() {
+            let frame = self.engine.frame();
+            let inputs: Vec<Input> = self.replay.inputs[self.next..]
+                .iter()
+                .take_while(|(f, _)| *f == frame)
+                .map(|&(_, input)| input)
+                .collect();
+            self.next += inputs.len();
+            events.extend(self.engine.step(&inputs));
         }
-        KeyCode::Char('z') => {
-            rotate_left(game);
+        if self.is_finished() {
+            self.position = self
+                .position
+                .min(gravity::FRAME * self.engine.frame() as u32);
         }
-        KeyCode::Char('x') => {
-            rotate_right(game);
+        events
+    }
+
+    // 巻き戻すときは最初から再生し直す
+    fn seek(&mut self, position: time::Duration) {
+        if position < self.position {
+            *self = Playback::new(self.replay);
         }
-        KeyCode::Char(' ') => {
-            hold(game);
+        self.advance(position - self.position);
+    }
+}
+
+// space: 一時停止, ↑↓: 再生速度, ←→: 5秒戻る・進む, q: 終了
+pub fn playback(replay: &Replay) -> Result<(), Box<dyn Error>> {
+    let mut playback = Playback::new(replay);
+    let mut ui = ui::Ui::new(&playback.engine.game.field)?;
+    let mut speed = 2;
+    let mut paused = false;
+
+    let mut last_update = time::Instant::now();
+    loop {
+        let state = if playback.is_finished() {
+            "END"
+        } else if paused {
+            "PAUSE"
+        } else {
+            ""
+        };
+        let status = format!("x{}\n{}", REPLAY_SPEEDS[speed], state);
+        ui.draw_replay(&playback.engine.game, &status)?;
+
+        if event::poll(gravity::FRAME)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    match key.code {
+                        KeyCode::Char(' ') => paused = !paused,
+                        KeyCode::Up => speed = (speed + 1).min(REPLAY_SPEEDS.len() - 1),
+                        KeyCode::Down => speed = speed.saturating_sub(1),
+                        KeyCode::Right => playback.seek(playback.position + REPLAY_SEEK),
+                        KeyCode::Left => {
+                            playback.seek(playback.position.saturating_sub(REPLAY_SEEK))
+                        }
+                        KeyCode::Char('q') => break,
+                        _ => (),
+                    }
+                }
+            }
         }
-        KeyCode::Char('q') => {
-            return Some(KeyInputProcessResult::QuitGame);
+
+        let now = time::Instant::now();
+        let elapsed = now - last_update;
+        last_update = now;
+        if !paused {
+            for event in playback.advance(elapsed.mul_f64(REPLAY_SPEEDS[speed])) {
+                ui.notify(&event);
+            }
         }
-        _ => (),
     }
 
-    None
+    ui.shutdown()
 }
 
-pub fn auto() -> Result<(), Box<dyn Error>> {
-    let mut game = Game::new();
-    let mut ui = ui::Ui::new()?;
+// AIが選んだ位置に置いてハードドロップする
+pub fn auto(game: Game, search_config: &SearchConfig) -> Result<(), Box<dyn Error>> {
+    let mut ui = ui::Ui::new(&game.field)?;
+    let mut engine = Engine::new(game, InputConfig::default());
 
     let wait_duration = time::Duration::from_millis(100);
+    let hard_drop = [
+        Input::Press(Action::HardDrop),
+        Input::Release(Action::HardDrop),
+    ];
 
     loop {
-        ui.draw(&game)?;
+        ui.draw(&engine.game)?;
 
         if !event::poll(wait_duration)? {
-            let elite = eval(&game);
-            game = elite;
-            ui.draw(&game)?;
+            engine.game = search(&engine.game, search_config);
+            ui.draw(&engine.game)?;
             thread::sleep(wait_duration);
-            hard_drop(&mut game);
-            if landing(&mut game).is_err() {
-                let _ = ui.game_over(&game);
+            for event in engine.step(&hard_drop) {
+                ui.notify(&event);
+            }
+            if engine.is_finished() {
+                let _ = ui.game_over(&engine.game);
                 return ui.shutdown();
             }
             continue;
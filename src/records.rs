@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{env, fs, io};
+use tetris::game::garbage::GarbageConfig;
+
+const RECORDS_FILE_NAME: &str = ".tetris_records.toml";
+
+// タイムを競うモード
+#[derive(Clone, Copy)]
+pub enum Race {
+    Sprint,
+    Dig(GarbageConfig),
+}
+
+impl Race {
+    // 記録を分ける単位(掘りは穴の位置の決め方と数も別にする)
+    fn key(self, lines: usize) -> String {
+        match self {
+            Race::Sprint => lines.to_string(),
+            Race::Dig(garbage) => format!("{}/{}/{}", lines, garbage.placement, garbage.holes),
+        }
+    }
+}
+
+// 自己ベストの記録(ホームディレクトリに保存する)
+#[derive(Default, Serialize, Deserialize)]
+pub struct Records {
+    #[serde(default)]
+    sprint: BTreeMap<String, u64>, // ライン数 -> クリアタイム(ミリ秒)
+    #[serde(default)]
+    dig: BTreeMap<String, u64>, // "ライン数/穴の位置/穴の数" -> クリアタイム
+}
+
+// ホームディレクトリ直下のファイル
+pub fn home_file(name: &str) -> Option<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(name))
+}
+
+impl Records {
+    // ファイルがない、読めない場合は記録なしとして扱う
+    pub fn load() -> Records {
+        home_file(RECORDS_FILE_NAME)
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| toml::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = home_file(RECORDS_FILE_NAME)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "home directory not found"))?;
+        let text =
+            toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, text)
+    }
+
+    fn times(&self, race: Race) -> &BTreeMap<String, u64> {
+        match race {
+            Race::Sprint => &self.sprint,
+            Race::Dig(_) => &self.dig,
+        }
+    }
+
+    fn times_mut(&mut self, race: Race) -> &mut BTreeMap<String, u64> {
+        match race {
+            Race::Sprint => &mut self.sprint,
+            Race::Dig(_) => &mut self.dig,
+        }
+    }
+
+    pub fn best_time(&self, race: Race, lines: usize) -> Option<Duration> {
+        self.times(race)
+            .get(&race.key(lines))
+            .map(|&millis| Duration::from_millis(millis))
+    }
+
+    // 自己ベストを更新したらtrue
+    pub fn update_time(&mut self, race: Race, lines: usize, time: Duration) -> bool {
+        let millis = time.as_millis() as u64;
+        let key = race.key(lines);
+        let times = self.times_mut(race);
+        if times.get(&key).is_some_and(|&best| best <= millis) {
+            return false;
+        }
+        times.insert(key, millis);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tetris::game::garbage::HolePlacement;
+
+    fn dig(placement: HolePlacement, holes: usize) -> Race {
+        Race::Dig(GarbageConfig { holes, placement })
+    }
+
+    #[test]
+    fn keep_best_time() {
+        let mut records = Records::default();
+        assert!(records.update_time(Race::Sprint, 40, Duration::from_secs(60)));
+        assert!(!records.update_time(Race::Sprint, 40, Duration::from_secs(70)));
+        assert!(records.update_time(Race::Sprint, 40, Duration::from_secs(50)));
+        assert_eq!(
+            records.best_time(Race::Sprint, 40),
+            Some(Duration::from_secs(50))
+        );
+        assert_eq!(records.best_time(Race::Sprint, 20), None);
+    }
+
+    #[test]
+    fn dig_records_per_garbage_config() {
+        let mut records = Records::default();
+        let clean = dig(HolePlacement::Clean, 1);
+        let messy = dig(HolePlacement::Messy, 1);
+        let messy2 = dig(HolePlacement::Messy, 2);
+        assert!(records.update_time(clean, 10, Duration::from_secs(10)));
+        assert!(records.update_time(messy, 10, Duration::from_secs(30)));
+        assert!(records.update_time(messy2, 10, Duration::from_secs(40)));
+        assert_eq!(records.best_time(clean, 10), Some(Duration::from_secs(10)));
+        assert_eq!(records.best_time(messy, 10), Some(Duration::from_secs(30)));
+        assert_eq!(records.best_time(messy2, 10), Some(Duration::from_secs(40)));
+        assert_eq!(records.best_time(Race::Sprint, 10), None);
+
+        let text = toml::to_string(&records).unwrap();
+        let loaded: Records = toml::from_str(&text).unwrap();
+        assert_eq!(loaded.best_time(messy, 10), Some(Duration::from_secs(30)));
+    }
+}
@@ -0,0 +1,178 @@
+use crate::records::home_file;
+use crate::save;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tetris::game::engine::{Input, InputConfig};
+use tetris::game::Game;
+
+const REPLAY_VERSION: u64 = 4;
+const LAST_REPLAY_FILE_NAME: &str = ".tetris_replay.json";
+
+// 開始時の状態とフレームごとの入力を記録しておき、同じ順にエンジンへ与えて再現する
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    version: u64,
+    pub game: Game,
+    pub input: InputConfig,
+    pub frames: u64,
+    pub inputs: Vec<(u64, Input)>, // (フレーム, 入力)
+}
+
+impl Replay {
+    pub fn new(game: &Game, input: InputConfig) -> Replay {
+        Replay {
+            version: REPLAY_VERSION,
+            game: game.clone(),
+            input,
+            frames: 0,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, frame: u64, inputs: &[Input]) {
+        self.inputs
+            .extend(inputs.iter().map(|&input| (frame, input)));
+        self.frames = frame + 1;
+    }
+}
+
+// 保存先を指定しなければ直前のゲームとしてホームディレクトリに保存する
+pub fn default_path() -> Option<PathBuf> {
+    home_file(LAST_REPLAY_FILE_NAME)
+}
+
+pub fn save(replay: &Replay, path: &Path) -> Result<(), Box<dyn Error>> {
+    fs::write(path, serde_json::to_string(replay)?)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<Replay, Box<dyn Error>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut value: Value = serde_json::from_str(&text)?;
+    let version = value["version"].as_u64().ok_or("replay has no version")?;
+    migrate(version, &mut value)?;
+    let mut replay: Replay = serde_json::from_value(value)?;
+    replay.game.relink_rotation_system();
+    Ok(replay)
+}
+
+// 古いバージョンのリプレイを現在の形式に変換する
+fn migrate(version: u64, replay: &mut Value) -> Result<(), Box<dyn Error>> {
+    match version {
+        // 2: 開始レベルを持っていなかった(記録しているのは開始時の状態)
+        2 => {
+            replay["game"]["start_level"] = replay["game"]["level"].clone();
+            migrate(3, replay)
+        }
+        3 => {
+            save::grow_buffer(&mut replay["game"]["field"])?;
+            migrate(4, replay)
+        }
+        REPLAY_VERSION => {
+            replay["version"] = REPLAY_VERSION.into();
+            Ok(())
+        }
+        _ => Err(format!("unsupported replay version {}", version).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tetris::game::engine::{Action, Engine};
+    use tetris::game::{rotation_system, GameConfig};
+
+    // 記録した入力をフレーム順にエンジンへ与える
+    fn play(replay: &Replay) -> Engine {
+        let mut engine = Engine::new(replay.game.clone(), replay.input);
+        for frame in 0..replay.frames {
+            let inputs: Vec<Input> = replay
+                .inputs
+                .iter()
+                .filter(|(f, _)| *f == frame)
+                .map(|&(_, input)| input)
+                .collect();
+            engine.step(&inputs);
+        }
+        engine
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut replay = Replay::new(
+            &Game::new(&GameConfig {
+                seed: 42,
+                rotation_system: rotation_system::builtin("srs+").unwrap(),
+                width: 6,
+                height: 12,
+                ..Default::default()
+            }),
+            InputConfig::default(),
+        );
+        let actions = [
+            Action::Left,
+            Action::RotateRight,
+            Action::Hold,
+            Action::HardDrop,
+        ];
+        for frame in 0..600 {
+            let inputs = match frame % 20 {
+                0 => vec![Input::Press(actions[frame as usize / 20 % actions.len()])],
+                10 => vec![Input::Release(actions[frame as usize / 20 % actions.len()])],
+                _ => Vec::new(),
+            };
+            replay.record(frame, &inputs);
+        }
+
+        let path =
+            std::env::temp_dir().join(format!("tetris_replay_test_{}.json", std::process::id()));
+        save(&replay, &path).unwrap();
+        let loaded = load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.frames, replay.frames);
+        assert_eq!(loaded.inputs, replay.inputs);
+        assert_eq!(loaded.game.field, replay.game.field);
+        assert_eq!(loaded.game.seed, replay.game.seed);
+
+        // 回転法則も読み込み直したもので同じ結果になる
+        let (a, b) = (play(&replay), play(&loaded));
+        assert!(a.game.statistics.pieces > 0);
+        assert_eq!(a.game.score, b.game.score);
+        assert_eq!(a.game.field, b.game.field);
+    }
+
+    #[test]
+    fn load_version_2() {
+        let game = Game::new(&GameConfig {
+            level: 4,
+            ..Default::default()
+        });
+        let mut value = serde_json::to_value(Replay::new(&game, InputConfig::default())).unwrap();
+        value["version"] = 2.into();
+        value["game"].as_object_mut().unwrap().remove("start_level");
+        let path =
+            std::env::temp_dir().join(format!("tetris_replay_v2_{}.json", std::process::id()));
+        fs::write(&path, value.to_string()).unwrap();
+        let loaded = load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().game.start_level, 4);
+    }
+
+    #[test]
+    fn reject_other_versions() {
+        let mut replay = Replay::new(&Game::new(&GameConfig::default()), InputConfig::default());
+        replay.version = REPLAY_VERSION + 1;
+        let path =
+            std::env::temp_dir().join(format!("tetris_replay_version_{}.json", std::process::id()));
+        save(&replay, &path).unwrap();
+        let loaded = load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use crate::records::home_file;
+use serde::Serialize;
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tetris::game::{cell, field, gravity, Game};
+
+// 保存形式を変えたら上げて、古い形式からの変換をmigrateに追加する
+const SAVE_VERSION: u64 = 3;
+const SAVE_FILE_NAME: &str = ".tetris_save.json";
+
+#[derive(Serialize)]
+struct SaveFile<'a> {
+    version: u64,
+    game: &'a Game,
+}
+
+fn save_path() -> Result<PathBuf, Box<dyn Error>> {
+    home_file(SAVE_FILE_NAME).ok_or_else(|| "home directory not found".into())
+}
+
+// 保存するのはGameだけで、エンジンの自動落下の間隔・DAS・ソフトドロップの状態は
+// 再開したときに最初から(キーをすべて離した状態)になる
+pub fn save(game: &Game) -> Result<(), Box<dyn Error>> {
+    save_to(game, &save_path()?)
+}
+
+pub fn load() -> Result<Game, Box<dyn Error>> {
+    load_from(&save_path()?)
+}
+
+fn save_to(game: &Game, path: &Path) -> Result<(), Box<dyn Error>> {
+    let save_file = SaveFile {
+        version: SAVE_VERSION,
+        game,
+    };
+    fs::write(path, serde_json::to_string(&save_file)?)?;
+    Ok(())
+}
+
+// 読み込んだセーブデータは削除する(中断したらまた保存される)
+fn load_from(path: &Path) -> Result<Game, Box<dyn Error>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut save_file: Value = serde_json::from_str(&text)?;
+    let version = save_file["version"]
+        .as_u64()
+        .ok_or("save file has no version")?;
+    let game = migrate(version, save_file["game"].take())?;
+    let mut game: Game = serde_json::from_value(game)?;
+    game.relink_rotation_system();
+    fs::remove_file(path)?;
+    Ok(game)
+}
+
+// 古いバージョンのセーブデータを現在の形式に変換する
+fn migrate(version: u64, mut game: Value) -> Result<Value, Box<dyn Error>> {
+    match version {
+        // 1: 開始レベルを持っていなかったので、今のレベルが変わらないように決める
+        1 => {
+            let level = game["level"].as_u64().ok_or("save file has no level")?;
+            let lines = game["lines"].as_u64().ok_or("save file has no lines")?;
+            let start_level = level.saturating_sub(lines / gravity::LINES_PER_LEVEL as u64);
+            game["start_level"] = start_level.max(1).into();
+            migrate(2, game)
+        }
+        2 => {
+            grow_buffer(&mut game["field"])?;
+            migrate(3, game)
+        }
+        SAVE_VERSION => Ok(game),
+        _ => Err(format!("unsupported save file version {}", version).into()),
+    }
+}
+
+// バッファ領域が可視領域と同じ高さだった頃のフィールドに、足りない段を上から足す
+pub fn grow_buffer(field: &mut Value) -> Result<(), Box<dyn Error>> {
+    let rows = field.as_array_mut().ok_or("field is not an array")?;
+    let width = rows.first().and_then(Value::as_array).map_or(0, Vec::len);
+    if width < 4 {
+        return Err("invalid field width".into());
+    }
+    let visible_height = rows.len().saturating_sub(2) / 2;
+    let missing = field::MIN_BUFFER_HEIGHT.saturating_sub(visible_height);
+    let mut line = vec![cell::NONE; width];
+    line[1] = cell::WALL;
+    line[width - 2] = cell::WALL;
+    rows.splice(0..0, (0..missing).map(|_| line.clone().into()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tetris::game::engine::{Action, Engine, Input, InputConfig};
+    use tetris::game::randomizer::RandomizerKind;
+    use tetris::game::{rotation_system, GameConfig};
+
+    #[test]
+    fn resume_game_in_progress() {
+        let mut engine = Engine::new(
+            Game::new(&GameConfig {
+                seed: 11,
+                randomizer: RandomizerKind::Bag14,
+                rotation_system: rotation_system::builtin("ars").unwrap(),
+                ..Default::default()
+            }),
+            InputConfig::default(),
+        );
+        // 何個か置いてホールドもしておく
+        let press = |action| [Input::Press(action), Input::Release(action)];
+        engine.step(&press(Action::Hold));
+        for action in [
+            Action::Left,
+            Action::RotateRight,
+            Action::Right,
+            Action::Left,
+        ] {
+            engine.step(&press(action));
+            engine.step(&press(Action::HardDrop));
+        }
+        engine.step(&press(Action::SoftDrop));
+        let game = engine.game;
+        assert!(game.statistics.pieces > 0);
+
+        let path =
+            std::env::temp_dir().join(format!("tetris_save_test_{}.json", std::process::id()));
+        save_to(&game, &path).unwrap();
+        let loaded = load_from(&path).unwrap();
+        assert!(!path.exists());
+
+        assert_eq!(loaded.field, game.field);
+        assert_eq!(loaded.pos, game.pos);
+        assert_eq!(loaded.score, game.score);
+        assert_eq!(
+            loaded.hold_tetromino.map(|t| t.kind()),
+            game.hold_tetromino.map(|t| t.kind())
+        );
+        let kinds = |game: &Game| {
+            game.next_tetrominoes
+                .iter()
+                .map(|t| t.kind())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(kinds(&loaded), kinds(&game));
+
+        // 続きを同じ操作で進めると同じ結果になる(乱数や回転法則も引き継ぐ)
+        let play = |game: Game| {
+            let mut engine = Engine::new(game, InputConfig::default());
+            for _ in 0..20 {
+                engine.step(&press(Action::RotateLeft));
+                engine.step(&press(Action::HardDrop));
+            }
+            engine.game
+        };
+        let (a, b) = (play(game), play(loaded));
+        assert_eq!(a.field, b.field);
+        assert_eq!(a.score, b.score);
+        assert_eq!(kinds(&a), kinds(&b));
+    }
+
+    #[test]
+    fn migrate_start_level() {
+        // 途中のレベルから始めたゲームは今のレベルを保つ
+        let game = migrate(
+            1,
+            json!({"level": 7, "lines": 23, "field": field::Field::default()}),
+        )
+        .unwrap();
+        assert_eq!(game["start_level"], 5);
+        let game = migrate(
+            1,
+            json!({"level": 3, "lines": 25, "field": field::Field::default()}),
+        )
+        .unwrap();
+        assert_eq!(game["start_level"], 1);
+        assert!(migrate(1, json!({})).is_err());
+        assert!(migrate(SAVE_VERSION + 1, json!({})).is_err());
+    }
+
+    #[test]
+    fn migrate_short_field() {
+        let field = field::Field::new(10, 8);
+        let mut game = json!({ "field": field });
+        // 以前はバッファ領域が可視領域と同じ8段だった
+        game["field"].as_array_mut().unwrap().drain(0..12);
+        let game = migrate(2, game).unwrap();
+        let migrated: field::Field = serde_json::from_value(game["field"].clone()).unwrap();
+        assert_eq!(migrated, field);
+
+        let field = field::Field::default();
+        let game = migrate(2, json!({ "field": field })).unwrap();
+        assert_eq!(game["field"], json!(field));
+    }
+}
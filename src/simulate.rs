@@ -0,0 +1,135 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use tetris::ai::search::{search, SearchConfig};
+use tetris::game::engine::{Action, Engine, Input, InputConfig};
+use tetris::game::{Game, GameConfig};
+
+// 1ゲームの打ち切り条件(どちらかに達したら終了)
+#[derive(Clone, Copy)]
+pub struct Limit {
+    pub pieces: Option<usize>,
+    pub time: Option<Duration>, // 実時間
+}
+
+impl Limit {
+    fn is_reached(&self, pieces: usize, elapsed: Duration) -> bool {
+        self.pieces.is_some_and(|limit| pieces >= limit)
+            || self.time.is_some_and(|limit| elapsed >= limit)
+    }
+}
+
+struct GameResult {
+    lines: usize,
+    score: usize,
+    pieces: usize,
+    topped_out: bool,
+}
+
+const HARD_DROP: [Input; 2] = [
+    Input::Press(Action::HardDrop),
+    Input::Release(Action::HardDrop),
+];
+
+// 画面を出さずにAIでゲームを進める
+fn play(config: &GameConfig, search_config: &SearchConfig, limit: Limit) -> GameResult {
+    let mut engine = Engine::new(Game::new(config), InputConfig::default());
+    let start = Instant::now();
+    while !engine.is_finished() && !limit.is_reached(engine.game.statistics.pieces, start.elapsed())
+    {
+        engine.game = search(&engine.game, search_config);
+        engine.step(&HARD_DROP);
+    }
+    let game = &engine.game;
+    GameResult {
+        lines: game.lines,
+        score: game.score,
+        pieces: game.statistics.pieces,
+        topped_out: game.statistics.game_over_reason.is_some(),
+    }
+}
+
+// i番目のゲームはシード+iで始めるので、スレッド数によらず同じ順番になる
+fn play_all(
+    config: &GameConfig,
+    search_config: &SearchConfig,
+    games: usize,
+    threads: usize,
+    limit: Limit,
+) -> Vec<GameResult> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(games));
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, games.max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= games {
+                    break;
+                }
+                let config = GameConfig {
+                    seed: config.seed.wrapping_add(i as u64),
+                    ..config.clone()
+                };
+                let result = play(&config, search_config, limit);
+                results.lock().unwrap().push((i, result));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+fn mean(values: &[usize]) -> f64 {
+    values.iter().sum::<usize>() as f64 / values.len() as f64
+}
+
+fn median(values: &[usize]) -> f64 {
+    let mut values = values.to_vec();
+    values.sort_unstable();
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) as f64 / 2.0
+    } else {
+        values[mid] as f64
+    }
+}
+
+pub fn simulate(
+    config: &GameConfig,
+    search_config: &SearchConfig,
+    games: usize,
+    threads: usize,
+    limit: Limit,
+) {
+    let start = Instant::now();
+    let results = play_all(config, search_config, games, threads, limit);
+    let elapsed = start.elapsed();
+    if results.is_empty() {
+        return;
+    }
+
+    let lines: Vec<usize> = results.iter().map(|result| result.lines).collect();
+    let score: Vec<usize> = results.iter().map(|result| result.score).collect();
+    let pieces: Vec<usize> = results.iter().map(|result| result.pieces).collect();
+    let topped_out = results.iter().filter(|result| result.topped_out).count();
+
+    println!(
+        "Games: {} ({} threads, {:.2}s, {:.0} pieces/s)",
+        results.len(),
+        threads.clamp(1, games),
+        elapsed.as_secs_f64(),
+        pieces.iter().sum::<usize>() as f64 / elapsed.as_secs_f64()
+    );
+    println!("{:<8}{:>12}{:>12}", "", "mean", "median");
+    for (name, values) in [("Lines", &lines), ("Score", &score), ("Pieces", &pieces)] {
+        println!("{:<8}{:>12.1}{:>12.1}", name, mean(values), median(values));
+    }
+    println!(
+        "Top-out rate: {:.1}% ({}/{})",
+        topped_out as f64 * 100.0 / results.len() as f64,
+        topped_out,
+        results.len()
+    );
+}
@@ -1,23 +1,30 @@
-use crate::game::{
-    cell, hard_drop_pos, tetromino::Tetromino, Game, FIELD_HEIGHT, FIELD_WIDTH,
-    NEXT_TETROMINOES_SIZE,
-};
 use crossterm::{
-    event::{self, Event},
+    event::{
+        self, Event, KeyEventKind, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags,
+        PushKeyboardEnhancementFlags,
+    },
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{
+        self, disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement,
+        EnterAlternateScreen, LeaveAlternateScreen,
+    },
 };
 use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 use std::{error::Error, io};
+use tetris::game::{
+    cell, event::GameEvent, field::Field, hard_drop_pos, score::TSpin, tetromino::Tetromino, Game,
+    GameOverReason, Goal, NEXT_TETROMINOES_SIZE,
+};
 use tui::{
     backend::{Backend, CrosstermBackend},
-    layout::{Alignment, Constraint, Direction, Layout, Margin, Rect},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Style},
     widgets::{Block, Borders, Paragraph, Widget},
     Frame, Terminal,
 };
 
-const BG_COLOR_TABLE: [Color; 10] = [
+const BG_COLOR_TABLE: [Color; 11] = [
     Color::Rgb(0, 0, 0),       // 何もなし
     Color::Rgb(127, 127, 127), // 壁
     Color::Rgb(0, 0, 0),       // ゴースト
@@ -28,6 +35,7 @@ const BG_COLOR_TABLE: [Color; 10] = [
     Color::Rgb(0, 0, 255),     // J
     Color::Rgb(255, 127, 0),   // L
     Color::Rgb(255, 0, 255),   // T
+    Color::Rgb(80, 80, 80),    // せり上がり
 ];
 
 fn get_cell_attribute(kind: cell::Kind) -> (&'static str, Style) {
@@ -39,20 +47,38 @@ fn get_cell_attribute(kind: cell::Kind) -> (&'static str, Style) {
     }
 }
 
+// 可視領域の上にはみ出して表示する段数
+const FIELD_PEEK_HEIGHT: usize = 2;
+
+// 消去の種類などを表示しておく時間
+const MESSAGE_DURATION: Duration = Duration::from_millis(1500);
+
 struct FieldWidget<'a> {
     game: &'a Game,
+    peek: usize,
 }
 
 impl<'a> FieldWidget<'a> {
-    fn new(game: &Game) -> FieldWidget {
-        FieldWidget { game }
+    fn new(game: &Game) -> FieldWidget<'_> {
+        FieldWidget { game, peek: 0 }
+    }
+
+    fn peek(mut self, peek: usize) -> FieldWidget<'a> {
+        self.peek = peek.min(self.game.field.buffer_height());
+        self
     }
 
-    pub fn calc_coordinate(area: Rect, x: usize, y: usize) -> (u16, u16) {
+    fn top(&self) -> usize {
+        self.game.field.buffer_height() - self.peek
+    }
+
+    // 表示範囲外ならNone
+    fn calc_coordinate(&self, area: Rect, x: usize, y: usize) -> Option<(u16, u16)> {
+        let y = y.checked_sub(self.top())?;
         let px = area.x + (x * 2 - 2) as u16;
         let py = area.y + y as u16;
 
-        (px, py)
+        Some((px, py))
     }
 }
 
@@ -61,11 +87,12 @@ impl<'a> Widget for FieldWidget<'a> {
         let game = &self.game;
 
         // フィールド
-        for y in 0..FIELD_HEIGHT - 1 {
-            for x in 1..FIELD_WIDTH - 1 {
-                let (px, py) = Self::calc_coordinate(area, x, y);
-                let (s, style) = get_cell_attribute(game.field[y][x] as usize);
-                buf.set_string(px, py, s, style);
+        for y in self.top()..game.field.height() - 1 {
+            for x in 1..game.field.width() - 1 {
+                if let Some((px, py)) = self.calc_coordinate(area, x, y) {
+                    let (s, style) = get_cell_attribute(game.field[y][x]);
+                    buf.set_string(px, py, s, style);
+                }
             }
         }
 
@@ -73,10 +100,13 @@ impl<'a> Widget for FieldWidget<'a> {
         let ghost_pos = hard_drop_pos(&game.field, &game.pos, &game.tetromino);
 
         // ゴースト
-        for y in 0..4 {
-            for x in 0..4 {
-                if shape[y][x] != cell::NONE {
-                    let (px, py) = Self::calc_coordinate(area, ghost_pos.x + x, ghost_pos.y + y);
+        for (y, line) in shape.iter().enumerate() {
+            for (x, &kind) in line.iter().enumerate() {
+                if kind == cell::NONE {
+                    continue;
+                }
+                if let Some((px, py)) = self.calc_coordinate(area, ghost_pos.x + x, ghost_pos.y + y)
+                {
                     let s = "[]";
                     let style = Style::default().fg(BG_COLOR_TABLE[game.tetromino.cell_kind()]);
                     buf.set_string(px, py, s, style);
@@ -85,11 +115,13 @@ impl<'a> Widget for FieldWidget<'a> {
         }
 
         // テトリミノ
-        for y in 0..4 {
-            for x in 0..4 {
-                if shape[y][x] != cell::NONE {
-                    let (px, py) = Self::calc_coordinate(area, game.pos.x + x, game.pos.y + y);
-                    let (s, style) = get_cell_attribute(shape[y][x] as usize);
+        for (y, line) in shape.iter().enumerate() {
+            for (x, &kind) in line.iter().enumerate() {
+                if kind == cell::NONE {
+                    continue;
+                }
+                if let Some((px, py)) = self.calc_coordinate(area, game.pos.x + x, game.pos.y + y) {
+                    let (s, style) = get_cell_attribute(kind);
                     buf.set_string(px, py, s, style);
                 }
             }
@@ -103,7 +135,7 @@ struct HoldTetrominoWidget<'a> {
 }
 
 impl<'a> HoldTetrominoWidget<'a> {
-    fn new(tetromino: &Option<Tetromino>) -> HoldTetrominoWidget {
+    fn new(tetromino: &Option<Tetromino>) -> HoldTetrominoWidget<'_> {
         HoldTetrominoWidget {
             block: None,
             tetromino,
@@ -131,11 +163,11 @@ impl<'a> Widget for HoldTetrominoWidget<'a> {
             Some(tetromino) => tetromino.get_shape(),
             None => Default::default(),
         };
-        for y in 0..4 {
-            for x in 0..4 {
+        for (y, line) in shape.iter().enumerate() {
+            for (x, &kind) in line.iter().enumerate() {
                 let px = area.x + (x * 2) as u16;
                 let py = area.y + y as u16;
-                let (s, style) = get_cell_attribute(shape[y][x]);
+                let (s, style) = get_cell_attribute(kind);
                 buf.set_string(px, py, s, style);
             }
         }
@@ -148,7 +180,7 @@ struct NextTetrominoesWidget<'a> {
 }
 
 impl<'a> NextTetrominoesWidget<'a> {
-    fn new(next_tetrominoes: &VecDeque<Tetromino>) -> NextTetrominoesWidget {
+    fn new(next_tetrominoes: &VecDeque<Tetromino>) -> NextTetrominoesWidget<'_> {
         NextTetrominoesWidget {
             block: None,
             next_tetrominoes,
@@ -179,11 +211,11 @@ impl<'a> Widget for NextTetrominoesWidget<'a> {
             .enumerate()
         {
             let shape = tetromino.get_shape();
-            for y in 0..4 {
-                for x in 0..4 {
+            for (y, line) in shape.iter().enumerate() {
+                for (x, &kind) in line.iter().enumerate() {
                     let px = area.x + (x * 2) as u16;
                     let py = area.y + (i * 4 + y) as u16;
-                    let (s, style) = get_cell_attribute(shape[y][x]);
+                    let (s, style) = get_cell_attribute(kind);
                     buf.set_string(px, py, s, style);
                 }
             }
@@ -199,48 +231,205 @@ struct GameLayout {
 
 pub struct Ui {
     terminal: Terminal<CrosstermBackend<io::Stdout>>,
-    game_layout: GameLayout,
+    keyboard_enhancement: bool,
+    message: Vec<String>,
+    message_at: Instant,
 }
 
 impl Ui {
-    pub fn new() -> Result<Ui, Box<dyn Error>> {
+    // 端末が狭すぎたらrawモードにする前にエラーにする
+    pub fn new(field: &Field) -> Result<Ui, Box<dyn Error>> {
+        let (width, height) = game_size(field);
+        let (columns, rows) = terminal::size()?;
+        if columns < width || rows < height {
+            return Err(format!(
+                "terminal too small: {}x{} needed, {}x{} available",
+                width, height, columns, rows
+            )
+            .into());
+        }
+
         enable_raw_mode()?;
         let mut stdout = io::stdout();
         execute!(stdout, EnterAlternateScreen)?;
+
+        // キーを離したことを通知してもらう(kittyキーボードプロトコル)
+        let keyboard_enhancement = supports_keyboard_enhancement().unwrap_or(false);
+        if keyboard_enhancement {
+            execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                        | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                        | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES
+                )
+            )?;
+        }
+
         let backend = CrosstermBackend::new(stdout);
         let terminal = Terminal::new(backend)?;
 
         Ok(Ui {
             terminal,
-            game_layout: create_game_layout(),
+            keyboard_enhancement,
+            message: Vec::new(),
+            message_at: Instant::now(),
         })
     }
 
+    // キーを離したイベントが届くか(Windowsは常に届く)
+    pub fn key_release_supported(&self) -> bool {
+        cfg!(windows) || self.keyboard_enhancement
+    }
+
     pub fn shutdown(self) -> Result<(), Box<dyn Error>> {
-        disable_raw_mode()?;
         let mut terminal = self.terminal;
+        if self.keyboard_enhancement {
+            execute!(terminal.backend_mut(), PopKeyboardEnhancementFlags)?;
+        }
+        disable_raw_mode()?;
         execute!(terminal.backend_mut(), LeaveAlternateScreen,)?;
         terminal.show_cursor()?;
         Ok(())
     }
 
     pub fn draw(&mut self, game: &Game) -> io::Result<()> {
-        self.terminal
-            .draw(|f| draw_game(f, &self.game_layout, &game))?;
+        let message = self.message();
+        self.terminal.draw(|f| {
+            draw_game(f, game, &message);
+        })?;
+        Ok(())
+    }
+
+    // イベントに応じて消去の種類などを表示する
+    pub fn notify(&mut self, event: &GameEvent) {
+        let mut message = Vec::new();
+        match event {
+            GameEvent::PieceLocked {
+                t_spin: Some(t_spin),
+                ..
+            } => {
+                message.push("T-SPIN".to_string());
+                if *t_spin == TSpin::Mini {
+                    message.push("MINI".to_string());
+                }
+            }
+            GameEvent::LinesCleared {
+                kind,
+                back_to_back,
+                perfect_clear,
+                ..
+            } => {
+                if *back_to_back {
+                    message.push("B2B".to_string());
+                }
+                message.extend(kind.name().split(' ').map(str::to_string));
+                if *perfect_clear {
+                    message.push("PERFECT".to_string());
+                }
+            }
+            GameEvent::ComboChanged { combo: Some(combo) } if *combo > 0 => {
+                // 同じピースの消去の表示に付け足す
+                self.message.push(format!("{} COMBO", combo));
+                return;
+            }
+            GameEvent::LevelUp { .. } => message.push("LEVEL UP".to_string()),
+            _ => return,
+        }
+        self.message = message;
+        self.message_at = Instant::now();
+    }
+
+    fn message(&self) -> String {
+        if self.message_at.elapsed() < MESSAGE_DURATION {
+            self.message.join("\n")
+        } else {
+            String::new()
+        }
+    }
+
+    // リプレイの再生状態を右下に表示する
+    pub fn draw_replay(&mut self, game: &Game, status: &str) -> io::Result<()> {
+        let message = self.message();
+        self.terminal.draw(|f| {
+            let Some(layout) = draw_game(f, game, &message) else {
+                return;
+            };
+            let status_box = Paragraph::new(status)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title("REPLAY")
+                        .title_alignment(Alignment::Center),
+                )
+                .alignment(Alignment::Center);
+            let mut area = layout.right_pane_chunks[1];
+            area.height = area.height.min(4);
+            f.render_widget(status_box, area);
+        })?;
         Ok(())
     }
 
     pub fn game_over(&mut self, game: &Game) -> io::Result<()> {
+        let reason = match game.statistics.game_over_reason {
+            Some(GameOverReason::BlockOut) => "BLOCK OUT",
+            Some(GameOverReason::LockOut) => "LOCK OUT",
+            Some(GameOverReason::TopOut) => "TOP OUT",
+            None => "",
+        };
+        let text = format!("GAME  OVER\n{}\nPIECES {}", reason, game.statistics.pieces);
+        self.dialog(game, &text)
+    }
+
+    // best: 今回の結果を反映する前の自己ベスト
+    pub fn race_result(
+        &mut self,
+        game: &Game,
+        best: Option<Duration>,
+        new_record: bool,
+    ) -> io::Result<()> {
+        let record = match best {
+            _ if new_record => "NEW RECORD".to_string(),
+            Some(best) => format!("BEST {}", format_time(best)),
+            None => String::new(),
+        };
+        let text = format!(
+            "FINISH\n{}\n{}\nPIECES {}",
+            format_time(game.elapsed),
+            record,
+            game.statistics.pieces
+        );
+        self.dialog(game, &text)
+    }
+
+    pub fn ultra_result(&mut self, game: &Game) -> io::Result<()> {
+        let text = format!(
+            "TIME  UP\nSCORE {}\nLINES {}\nPIECES {}",
+            game.score, game.lines, game.statistics.pieces
+        );
+        self.dialog(game, &text)
+    }
+
+    // キーが押されるまでメッセージを表示する
+    fn dialog(&mut self, game: &Game, text: &str) -> io::Result<()> {
         loop {
             self.terminal.draw(|f| {
-                draw_game(f, &self.game_layout, &game);
-                let mut dialog_area = self.game_layout.center_pane_chunks[0].inner(&Margin {
-                    vertical: 10,
-                    horizontal: 5,
-                });
-                dialog_area.height = 3;
-
-                let dialog = Paragraph::new("GAME  OVER")
+                let Some(layout) = draw_game(f, game, "") else {
+                    return;
+                };
+                // 標準の大きさでは左右3マスずつ空け、狭いフィールドでは文字が収まるようにはみ出す
+                let field_area = layout.center_pane_chunks[0];
+                let text_width = text.lines().map(str::len).max().unwrap_or(0) as u16;
+                let mut dialog_area = field_area;
+                dialog_area.width = (text_width + 4).max(field_area.width.saturating_sub(6));
+                dialog_area.x =
+                    (field_area.x + field_area.width / 2).saturating_sub(dialog_area.width / 2);
+                dialog_area.height = text.lines().count() as u16 + 2;
+                // 標準の大きさで上から10段目になる位置(はみ出す場合は下端に合わせる)
+                dialog_area.y += (field_area.height * 5 / 12)
+                    .min(field_area.height.saturating_sub(dialog_area.height));
+
+                let dialog = Paragraph::new(text)
                     .block(Block::default().borders(Borders::ALL))
                     .style(Style::default().fg(Color::White).bg(Color::Black))
                     .alignment(Alignment::Center);
@@ -249,8 +438,10 @@ impl Ui {
                 f.render_widget(dialog, dialog_area);
             })?;
 
-            if let Event::Key(_) = event::read()? {
-                break;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    break;
+                }
             }
         }
 
@@ -258,22 +449,44 @@ impl Ui {
     }
 }
 
-fn create_game_layout() -> GameLayout {
+// フィールドの表示に使う幅と高さ
+fn field_size(field: &Field) -> (u16, u16) {
+    let width = (field.width() as u16 - 2) * 2;
+    let height = (FIELD_PEEK_HEIGHT.min(field.buffer_height()) + field.visible_height()) as u16 + 1;
+    (width, height)
+}
+
+// 画面全体の表示に必要な幅と高さ
+fn game_size(field: &Field) -> (u16, u16) {
+    let (field_width, field_height) = field_size(field);
+    (field_width + 20, field_height.max(23) + 1)
+}
+
+// フィールドの大きさに合わせて中央の幅と全体の高さを決める
+fn create_game_layout(field: &Field) -> GameLayout {
+    let (field_width, field_height) = field_size(field);
+    let (width, height) = game_size(field);
     let chunks = Layout::default()
         .direction(Direction::Horizontal)
         .constraints(
             [
                 Constraint::Length(10),
-                Constraint::Length(24),
+                Constraint::Length(field_width),
                 Constraint::Length(10),
                 Constraint::Percentage(100),
             ]
             .as_ref(),
         )
-        .split(Rect::new(0, 0, 80, 24));
+        .split(Rect::new(0, 0, width, height));
     let center_pane_chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Length(24), Constraint::Percentage(100)].as_ref())
+        .constraints(
+            [
+                Constraint::Length(field_height + 1),
+                Constraint::Percentage(100),
+            ]
+            .as_ref(),
+        )
         .split(chunks[1]);
     let left_pane_chunks = Layout::default()
         .direction(Direction::Vertical)
@@ -281,6 +494,9 @@ fn create_game_layout() -> GameLayout {
             [
                 Constraint::Length(6),
                 Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Length(3),
                 Constraint::Percentage(100),
             ]
             .as_ref(),
@@ -298,8 +514,41 @@ fn create_game_layout() -> GameLayout {
     }
 }
 
-fn draw_game<B: Backend>(f: &mut Frame<B>, layout: &GameLayout, game: &Game) {
-    let field = FieldWidget::new(&game);
+fn status_box(title: &str, value: String) -> Paragraph<'_> {
+    Paragraph::new(value)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(title)
+                .title_alignment(Alignment::Center),
+        )
+        .style(Style::default())
+        .alignment(Alignment::Right)
+}
+
+// m:ss.mmm
+fn format_time(time: Duration) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{}:{:02}.{:03}",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+// 途中で端末が縮んで収まらなくなったら代わりに案内だけ表示してNoneを返す
+fn draw_game<B: Backend>(f: &mut Frame<B>, game: &Game, message: &str) -> Option<GameLayout> {
+    let (width, height) = game_size(&game.field);
+    let area = f.size();
+    if area.width < width || area.height < height {
+        let text = format!("TERMINAL TOO SMALL\n{}x{} needed", width, height);
+        f.render_widget(Paragraph::new(text).alignment(Alignment::Center), area);
+        return None;
+    }
+
+    let layout = create_game_layout(&game.field);
+    let field = FieldWidget::new(game).peek(FIELD_PEEK_HEIGHT);
     let box_border = Block::default()
         .borders(Borders::ALL)
         .title_alignment(Alignment::Center);
@@ -307,18 +556,32 @@ fn draw_game<B: Backend>(f: &mut Frame<B>, layout: &GameLayout, game: &Game) {
         HoldTetrominoWidget::new(&game.hold_tetromino).block(box_border.clone().title("HOLD"));
     let next_tetrominoes_box =
         NextTetrominoesWidget::new(&game.next_tetrominoes).block(box_border.title("NEXT"));
-    let score_box = Paragraph::new(game.score.to_string())
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .title("SCORE")
-                .title_alignment(Alignment::Center),
-        )
-        .style(Style::default())
-        .alignment(Alignment::Right);
+    let score_box = status_box("SCORE", game.score.to_string());
+    let level_box = status_box("LEVEL", game.level.to_string());
+    let lines = match game.goal {
+        Goal::Lines(goal) => format!("{}/{}", game.lines.min(goal), goal),
+        Goal::Garbage(goal) => format!("{}/{}", game.garbage.cleared.min(goal), goal),
+        _ => game.lines.to_string(),
+    };
+    let lines_box = status_box("LINES", lines);
+    // 制限時間があれば残り時間を表示する
+    let time = match game.goal {
+        Goal::Time(limit) => limit.saturating_sub(game.elapsed),
+        _ => game.elapsed,
+    };
+    let time_box = status_box("TIME", format_time(time));
 
     f.render_widget(field, layout.center_pane_chunks[0]);
     f.render_widget(hold_tetromino_box, layout.left_pane_chunks[0]);
     f.render_widget(score_box, layout.left_pane_chunks[1]);
+    f.render_widget(level_box, layout.left_pane_chunks[2]);
+    f.render_widget(lines_box, layout.left_pane_chunks[3]);
+    f.render_widget(time_box, layout.left_pane_chunks[4]);
+    f.render_widget(
+        Paragraph::new(message).alignment(Alignment::Center),
+        layout.left_pane_chunks[5],
+    );
     f.render_widget(next_tetrominoes_box, layout.right_pane_chunks[0]);
+
+    Some(layout)
 }